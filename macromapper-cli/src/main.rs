@@ -0,0 +1,99 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use macromapper_core::{file::load_map, grid::GridMode, svg::map_to_svg};
+
+mod raster;
+use raster::*;
+
+const USAGE: &str = "\
+Renders a saved Macromapper map without opening a window.
+
+Usage: macromapper-cli [options] <map.mmap> <output.png|output.svg>
+
+Options:
+  --pixels-per-square <n>   PNG resolution (default 50)
+  --grid <full|inside|none> where to draw the grid (default: as saved in the map)
+  --no-grid                 same as --grid none
+  -h, --help                show this message";
+
+struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    pixels_per_square: f32,
+    //None keeps the map's own mode
+    grid: Option<GridMode>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut paths = vec![];
+    let mut pixels_per_square = 50.;
+    let mut grid = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            "--no-grid" => grid = Some(GridMode::Hidden),
+            "--grid" => {
+                grid = Some(match args.next().as_deref() {
+                    Some("full") => GridMode::Full,
+                    Some("inside") => GridMode::InsideOnly,
+                    Some("none") => GridMode::Hidden,
+                    _ => return Err("--grid needs one of full, inside or none".to_string()),
+                });
+            }
+            "--pixels-per-square" => {
+                pixels_per_square = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n: &f32| *n > 0.)
+                    .ok_or("--pixels-per-square needs a positive number")?;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}\n\n{USAGE}")),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    match <[PathBuf; 2]>::try_from(paths) {
+        Ok([input, output]) => Ok(Args {
+            input,
+            output,
+            pixels_per_square,
+            grid,
+        }),
+        Err(_) => Err(USAGE.to_string()),
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut map, _) = load_map(&args.input)?;
+    //there's no frame loop to wait on a Hatcher, so hatch everything up front
+    map.regenerate_hatching();
+    let grid = args.grid.unwrap_or(map.grid_settings().mode);
+    match args.output.extension().and_then(|e| e.to_str()) {
+        Some("png") => {
+            rasterize(&map, args.pixels_per_square, grid)?.save_png(&args.output)?;
+        }
+        Some("svg") => {
+            let svg = map_to_svg(&map, grid).ok_or(RasterError::EmptyMap)?;
+            std::fs::write(&args.output, svg)?;
+        }
+        _ => return Err("output must end in .png or .svg".into()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
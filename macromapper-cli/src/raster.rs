@@ -0,0 +1,175 @@
+use std::fmt;
+
+use macromapper_core::{
+    grid::GridMode,
+    map::Map,
+    sketch::{self, Canvas, Fill, Sketch},
+    svg::export_rect,
+};
+use tiny_skia::{Color, FillRule, LineCap, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+#[derive(Debug)]
+pub enum RasterError {
+    EmptyMap,
+    TooLarge(u32, u32),
+}
+
+impl fmt::Display for RasterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RasterError::EmptyMap => write!(f, "there is nothing on the map to render"),
+            RasterError::TooLarge(w, h) => write!(f, "a {w} x {h} px image is too large"),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+//Software version of the on-screen renderer: the same sketches in the same order,
+//covering the same area as the app's PNG export
+pub fn rasterize(map: &Map, pixels_per_square: f32, grid: GridMode) -> Result<Pixmap, RasterError> {
+    let world = export_rect(map).ok_or(RasterError::EmptyMap)?;
+    let scale = pixels_per_square / map.grid_settings().size;
+    let width = (world.width() * scale).ceil() as u32;
+    let height = (world.height() * scale).ceil() as u32;
+    let mut pixmap = Pixmap::new(width, height).ok_or(RasterError::TooLarge(width, height))?;
+    pixmap.fill(Color::WHITE);
+
+    //world y points up, image y points down
+    let transform = Transform::from_row(
+        scale,
+        0.,
+        0.,
+        -scale,
+        -world.min().x * scale,
+        world.max().y * scale,
+    );
+    map.draw(
+        map.grid(world, grid).as_ref(),
+        &mut PixmapCanvas {
+            pixmap: &mut pixmap,
+            transform,
+        },
+    );
+    Ok(pixmap)
+}
+
+struct PixmapCanvas<'a> {
+    pixmap: &'a mut Pixmap,
+    transform: Transform,
+}
+
+impl Canvas for PixmapCanvas<'_> {
+    fn fill(&mut self, fill: &Fill) {
+        fill_polygons(self.pixmap, fill, self.transform);
+    }
+    fn sketch(&mut self, sketch: &Sketch) {
+        stroke_sketch(self.pixmap, sketch, self.transform);
+    }
+}
+
+fn stroke_sketch(pixmap: &mut Pixmap, sketch: &Sketch, transform: Transform) {
+    let mut path = PathBuilder::new();
+    for line in sketch.lines() {
+        path.move_to(line.start().x, line.start().y);
+        path.line_to(line.end().x, line.end().y);
+    }
+    //an empty builder gives no path
+    let Some(path) = path.finish() else {
+        return;
+    };
+    let paint = paint(sketch.color());
+    let stroke = Stroke {
+        width: sketch.thickness(),
+        line_cap: LineCap::Round,
+        ..Default::default()
+    };
+    pixmap.stroke_path(&path, &paint, &stroke, transform, None);
+}
+
+fn fill_polygons(pixmap: &mut Pixmap, fill: &Fill, transform: Transform) {
+    let mut path = PathBuilder::new();
+    for polygon in fill.polygons() {
+        for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+            let mut points = ring.points();
+            let Some(first) = points.next() else {
+                continue;
+            };
+            path.move_to(first.x(), first.y());
+            for point in points {
+                path.line_to(point.x(), point.y());
+            }
+            path.close();
+        }
+    }
+    let Some(path) = path.finish() else {
+        return;
+    };
+    pixmap.fill_path(
+        &path,
+        &paint(fill.color()),
+        FillRule::EvenOdd,
+        transform,
+        None,
+    );
+}
+
+fn paint(color: sketch::Color) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(
+        (color.r * 255.).round() as u8,
+        (color.g * 255.).round() as u8,
+        (color.b * 255.).round() as u8,
+        (color.a * 255.).round() as u8,
+    );
+    paint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::polygon;
+    use macromapper_core::map::*;
+    use undo::Record;
+
+    #[test]
+    fn rasterize_square_room() {
+        let mut map = Map::new();
+        map.append_layer();
+        assert!(matches!(
+            rasterize(&map, 50., GridMode::Full),
+            Err(RasterError::EmptyMap)
+        ));
+
+        let mut history = Record::<MapEdit>::new();
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(
+                0,
+                geo::MultiPolygon::new(vec![polygon![
+                    (x: 0., y: 0.),
+                    (x: 0., y: 100.),
+                    (x: 100., y: 100.),
+                    (x: 100., y: 0.)
+                ]]),
+            )),
+        );
+        map.regenerate_hatching();
+        //100 units of room plus a 100 unit margin on each side, at one pixel per unit
+        let pixmap = rasterize(&map, 50., GridMode::Hidden).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (300, 300));
+        //the inside of the room stays white
+        let inside = pixmap.pixel(150, 150).unwrap();
+        assert_eq!(
+            (inside.red(), inside.green(), inside.blue()),
+            (255, 255, 255)
+        );
+        //the left wall is drawn in black
+        let wall = pixmap.pixel(100, 150).unwrap();
+        assert_eq!((wall.red(), wall.green(), wall.blue()), (0, 0, 0));
+
+        //output is deterministic, so it can be compared against golden images
+        let again = rasterize(&map, 50., GridMode::Hidden).unwrap();
+        assert_eq!(pixmap.data(), again.data());
+    }
+}
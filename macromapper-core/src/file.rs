@@ -0,0 +1,987 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    grid::{GridKind, GridMode, GridSettings},
+    hatching::{HatchPattern, HatchStyle},
+    map::*,
+    sketch::Color,
+};
+use geo::MultiPolygon;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use undo::Record;
+
+//Bump this whenever the layout of MapFile changes, and add a step to MIGRATIONS
+//that upgrades documents from the previous version
+pub const FILE_VERSION: u64 = 9;
+pub const FILE_EXTENSION: &str = "mmap";
+const FORMAT_NAME: &str = "macromapper";
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MapFile<H> {
+    header: Header,
+    map: MapData<H>,
+}
+
+//Only the source geometry is stored: sketches and hatching are regenerated on load,
+//so they never have to stay compatible between versions
+#[derive(Serialize, Deserialize)]
+struct MapData<H> {
+    layers: Vec<LayerFile>,
+    grid: GridFile,
+    history: H,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GridFile {
+    kind: GridKind,
+    size: f32,
+    distance: f32,
+    unit: String,
+    mode: GridMode,
+}
+
+impl From<&GridSettings> for GridFile {
+    fn from(grid: &GridSettings) -> Self {
+        GridFile {
+            kind: grid.kind,
+            size: grid.size,
+            distance: grid.distance,
+            unit: grid.unit.clone(),
+            mode: grid.mode,
+        }
+    }
+}
+
+impl From<GridFile> for GridSettings {
+    fn from(file: GridFile) -> Self {
+        GridSettings {
+            kind: file.kind,
+            size: file.size,
+            distance: file.distance,
+            unit: file.unit,
+            mode: file.mode,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LayerFile {
+    name: String,
+    area: MultiPolygon,
+    style: StyleFile,
+    hatch: HatchFile,
+    visible: bool,
+    locked: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StyleFile {
+    outline_thickness: f32,
+    outline_color: [f32; 4],
+    hatch_thickness: f32,
+    hatch_color: [f32; 4],
+    floor_color: [f32; 4],
+}
+
+#[derive(Serialize, Deserialize)]
+struct HatchFile {
+    pattern: HatchPattern,
+    radius: f64,
+    offset: f64,
+    hatch_count: i32,
+    seed: u64,
+    min_length: f64,
+}
+
+impl From<&Layer> for LayerFile {
+    fn from(layer: &Layer) -> Self {
+        let style = layer.style();
+        LayerFile {
+            name: layer.name().to_string(),
+            area: layer.area().clone(),
+            style: StyleFile {
+                outline_thickness: style.outline_thickness,
+                outline_color: style.outline_color.into(),
+                hatch_thickness: style.hatch_thickness,
+                hatch_color: style.hatch_color.into(),
+                floor_color: style.floor_color.into(),
+            },
+            hatch: HatchFile {
+                pattern: layer.hatch().pattern,
+                radius: layer.hatch().radius,
+                offset: layer.hatch().offset,
+                hatch_count: layer.hatch().hatch_count,
+                seed: layer.hatch().seed,
+                min_length: layer.hatch().min_length,
+            },
+            visible: layer.visible(),
+            locked: layer.locked(),
+        }
+    }
+}
+
+impl From<LayerFile> for Layer {
+    fn from(file: LayerFile) -> Self {
+        let style = LayerStyle {
+            outline_thickness: file.style.outline_thickness,
+            outline_color: Color::from(file.style.outline_color),
+            hatch_thickness: file.style.hatch_thickness,
+            hatch_color: Color::from(file.style.hatch_color),
+            floor_color: Color::from(file.style.floor_color),
+        };
+        let mut layer = Layer::with_area(file.name, style, file.area);
+        layer.set_flags(file.visible, file.locked);
+        layer.set_hatch(HatchStyle {
+            pattern: file.hatch.pattern,
+            radius: file.hatch.radius,
+            offset: file.hatch.offset,
+            hatch_count: file.hatch.hatch_count,
+            seed: file.hatch.seed,
+            min_length: file.hatch.min_length,
+        });
+        layer
+    }
+}
+
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    NotAMap,
+    UnsupportedVersion(u64),
+    InvalidGeometry { layer: String, reason: &'static str },
+    InvalidGrid(&'static str),
+    InvalidHistory { entry: usize, reason: &'static str },
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Io(e) => write!(f, "could not access file: {e}"),
+            FileError::Format(e) => write!(f, "file is not a valid map: {e}"),
+            FileError::NotAMap => write!(f, "file is not a Macromapper map"),
+            FileError::UnsupportedVersion(v) => write!(
+                f,
+                "map was saved in format version {v}, but this version of Macromapper only \
+                 supports up to {FILE_VERSION}. Try updating Macromapper"
+            ),
+            FileError::InvalidGeometry { layer, reason } => {
+                write!(f, "layer \"{layer}\" has corrupt geometry: {reason}")
+            }
+            FileError::InvalidGrid(reason) => write!(f, "map has a corrupt grid: {reason}"),
+            FileError::InvalidHistory { entry, reason } => {
+                write!(f, "undo history entry {entry} is corrupt: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        FileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for FileError {
+    fn from(e: serde_json::Error) -> Self {
+        FileError::Format(e)
+    }
+}
+
+pub fn save_map(path: &Path, map: &Map, history: &Record<MapEdit>) -> Result<(), FileError> {
+    let file = MapFile {
+        header: Header {
+            format: FORMAT_NAME.to_string(),
+            version: FILE_VERSION,
+        },
+        map: MapData {
+            layers: map.layers_iter().map(LayerFile::from).collect(),
+            grid: GridFile::from(map.grid_settings()),
+            history,
+        },
+    };
+    //written next to the target and renamed over it, so a crash halfway through never
+    //leaves a truncated map or recovery file behind
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, serde_json::to_string(&file)?)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+pub fn load_map(path: &Path) -> Result<(Map, Record<MapEdit>), FileError> {
+    parse_map(&fs::read_to_string(path)?)
+}
+
+fn parse_map(text: &str) -> Result<(Map, Record<MapEdit>), FileError> {
+    let mut document: Value = serde_json::from_str(text)?;
+    let version = document_version(&document)?;
+    if version == 0 {
+        return Err(FileError::NotAMap);
+    }
+    if version > FILE_VERSION {
+        return Err(FileError::UnsupportedVersion(version));
+    }
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        document = migration(document)?;
+    }
+    let file: MapFile<Record<MapEdit>> = serde_json::from_value(document)?;
+    for layer in &file.map.layers {
+        validate_area(&layer.area).map_err(|reason| FileError::InvalidGeometry {
+            layer: layer.name.clone(),
+            reason,
+        })?;
+    }
+    validate_grid(&file.map.grid).map_err(FileError::InvalidGrid)?;
+    validate_history(&file.map.history, file.map.layers.len())?;
+    let map = Map::from_layers(
+        file.map.layers.into_iter().map(Layer::from).collect(),
+        GridSettings::from(file.map.grid),
+    );
+    let mut history = file.map.history;
+    history.set_saved(true);
+    Ok((map, history))
+}
+
+fn document_version(document: &Value) -> Result<u64, FileError> {
+    match document.get("header") {
+        Some(header) => {
+            if header.get("format").and_then(Value::as_str) != Some(FORMAT_NAME) {
+                return Err(FileError::NotAMap);
+            }
+            header
+                .get("version")
+                .and_then(Value::as_u64)
+                .ok_or(FileError::NotAMap)
+        }
+        //version 1 predates the header and only had a bare version number
+        None => document
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or(FileError::NotAMap),
+    }
+}
+
+//MIGRATIONS[n] upgrades a document from version n + 1 to version n + 2.
+//Migrations work on raw JSON so that they don't depend on the current structs.
+const MIGRATIONS: [fn(Value) -> Result<Value, FileError>; (FILE_VERSION - 1) as usize] = [
+    v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9,
+];
+
+//v2 moved the version number into a header and nested the map data under it
+fn v1_to_v2(mut document: Value) -> Result<Value, FileError> {
+    let document = document.as_object_mut().ok_or(FileError::NotAMap)?;
+    Ok(json!({
+        "header": {
+            "format": FORMAT_NAME,
+            "version": 2,
+        },
+        "map": {
+            "layers": document.remove("layers").ok_or(FileError::NotAMap)?,
+            "history": document.remove("history").ok_or(FileError::NotAMap)?,
+        },
+    }))
+}
+
+//v3 stored whether each layer is shown and locked, and made adding, removing and reordering
+//layers undoable. Layers in older files were always visible and unlocked, including the
+//ones stored in history entries.
+fn v2_to_v3(mut document: Value) -> Result<Value, FileError> {
+    upgrade_layer_flags(&mut document);
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(3);
+    Ok(document)
+}
+
+fn upgrade_layer_flags(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if ["name", "area", "style"]
+                .iter()
+                .all(|k| object.contains_key(*k))
+            {
+                object.entry("visible").or_insert(json!(true));
+                object.entry("locked").or_insert(json!(false));
+            }
+            object.values_mut().for_each(upgrade_layer_flags);
+        }
+        Value::Array(array) => array.iter_mut().for_each(upgrade_layer_flags),
+        _ => {}
+    }
+}
+
+//v4 grouped the hatching pattern with the rest of the hatching parameters. Layers are also
+//stored inside history entries, so every layer in the document gets upgraded, and pattern
+//edits become hatch edits.
+fn v3_to_v4(mut document: Value) -> Result<Value, FileError> {
+    upgrade_hatching(&mut document);
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(4);
+    Ok(document)
+}
+
+fn v3_hatch(pattern: Option<Value>) -> Value {
+    json!({
+        "pattern": pattern.unwrap_or(json!("Lines")),
+        "radius": 20.0,
+        "offset": 50.0,
+        "hatch_count": 10,
+        "seed": 0x5EED,
+        "min_length": 7.0,
+    })
+}
+
+fn upgrade_hatching(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            //only layers have all three of these
+            if ["name", "area", "style"]
+                .iter()
+                .all(|k| object.contains_key(*k))
+            {
+                let pattern = object.remove("pattern");
+                object.insert("hatch".to_string(), v3_hatch(pattern));
+            }
+            if let Some(mut edit) = object.remove("PatternLayer") {
+                let pattern = edit.as_object_mut().and_then(|e| e.remove("pattern"));
+                edit["hatch"] = v3_hatch(pattern);
+                object.insert("HatchLayer".to_string(), edit);
+            }
+            object.values_mut().for_each(upgrade_hatching);
+        }
+        Value::Array(array) => array.iter_mut().for_each(upgrade_hatching),
+        _ => {}
+    }
+}
+
+//v5 gave layers a floor color. Floors used to always be white. Styles are also stored in style
+//edits and removed layers, so every style in the document gets one.
+fn v4_to_v5(mut document: Value) -> Result<Value, FileError> {
+    upgrade_floor(&mut document);
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(5);
+    Ok(document)
+}
+
+fn upgrade_floor(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            //only styles have these
+            if ["outline_thickness", "hatch_color"]
+                .iter()
+                .all(|k| object.contains_key(*k))
+            {
+                let white: [f32; 4] = LayerStyle::default().floor_color.into();
+                object.entry("floor_color").or_insert(json!(white));
+            }
+            object.values_mut().for_each(upgrade_floor);
+        }
+        Value::Array(array) => array.iter_mut().for_each(upgrade_floor),
+        _ => {}
+    }
+}
+
+//v6 grouped the grid shape with its cell size and real-world units. Up to v5 every map
+//used 50 unit cells and had no units, so the defaults fill in the rest. Grid edits in the
+//history carried just the shape, and get the same treatment.
+fn v5_to_v6(mut document: Value) -> Result<Value, FileError> {
+    let map = document
+        .get_mut("map")
+        .and_then(Value::as_object_mut)
+        .ok_or(FileError::NotAMap)?;
+    let kind = map.remove("grid_kind");
+    map.insert("grid".to_string(), v5_grid(kind));
+    upgrade_grid(&mut document);
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(6);
+    Ok(document)
+}
+
+fn v5_grid(kind: Option<Value>) -> Value {
+    let defaults = GridSettings::default();
+    json!({
+        "kind": kind.unwrap_or_else(|| json!(defaults.kind)),
+        "size": defaults.size,
+        "distance": defaults.distance,
+        "unit": defaults.unit,
+    })
+}
+
+fn upgrade_grid(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(edit) = object.get_mut("ChangeGrid").and_then(Value::as_object_mut) {
+                let kind = edit.remove("grid_kind");
+                edit.insert("grid".to_string(), v5_grid(kind));
+            }
+            object.values_mut().for_each(upgrade_grid);
+        }
+        Value::Array(array) => array.iter_mut().for_each(upgrade_grid),
+        _ => {}
+    }
+}
+
+//v7 added moving part of a layer to the history. Nothing older has to change, the bump is
+//so that older builds say the file is too new instead of failing to read it.
+fn v6_to_v7(mut document: Value) -> Result<Value, FileError> {
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(7);
+    Ok(document)
+}
+
+//v8 added reshaping part of a layer to the history, which only needs the bump, like v7
+fn v7_to_v8(mut document: Value) -> Result<Value, FileError> {
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(8);
+    Ok(document)
+}
+
+//v9 saved how much of the grid is shown along with the rest of the grid. Older maps
+//always opened with the full grid
+fn v8_to_v9(mut document: Value) -> Result<Value, FileError> {
+    document
+        .pointer_mut("/map/grid")
+        .and_then(Value::as_object_mut)
+        .ok_or(FileError::NotAMap)?
+        .insert("mode".to_string(), json!(GridMode::Full));
+    upgrade_grid_mode(&mut document);
+    *document
+        .pointer_mut("/header/version")
+        .ok_or(FileError::NotAMap)? = json!(9);
+    Ok(document)
+}
+
+fn upgrade_grid_mode(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(grid) = object
+                .get_mut("ChangeGrid")
+                .and_then(|edit| edit.get_mut("grid"))
+                .and_then(Value::as_object_mut)
+            {
+                grid.insert("mode".to_string(), json!(GridMode::Full));
+            }
+            object.values_mut().for_each(upgrade_grid_mode);
+        }
+        Value::Array(array) => array.iter_mut().for_each(upgrade_grid_mode),
+        _ => {}
+    }
+}
+
+//Edits before the head are applied to the saved map and get undone back to front, the rest get
+//redone front to back, so the layer count is followed both ways from the saved one
+fn validate_history(history: &Record<MapEdit>, layer_count: usize) -> Result<(), FileError> {
+    let entries: Vec<&MapEdit> = history.entries().map(|e| e.get()).collect();
+    let check = |entry: usize, count: isize, applied: bool| {
+        let edit = entries[entry];
+        let invalid = |reason| FileError::InvalidHistory { entry, reason };
+        if count < 0 {
+            return Err(invalid("more layers removed than there are"));
+        }
+        edit.check(count as usize, applied).map_err(invalid)?;
+        for area in edit.areas() {
+            validate_area(area).map_err(invalid)?;
+        }
+        if let Some(grid) = edit.grid() {
+            validate_grid(&GridFile::from(grid)).map_err(invalid)?;
+        }
+        Ok(())
+    };
+    let head = history.head();
+    if head > entries.len() {
+        return Err(FileError::InvalidHistory {
+            entry: head,
+            reason: "current position is past the last entry",
+        });
+    }
+    let mut count = layer_count as isize;
+    for (entry, edit) in entries.iter().enumerate().take(head).rev() {
+        count -= edit.layer_delta();
+        check(entry, count, true)?;
+    }
+    let mut count = layer_count as isize;
+    for (entry, edit) in entries.iter().enumerate().skip(head) {
+        check(entry, count, false)?;
+        count += edit.layer_delta();
+    }
+    Ok(())
+}
+
+//A zero or infinite cell size would have the grid drawing forever
+fn validate_grid(grid: &GridFile) -> Result<(), &'static str> {
+    if !(grid.size.is_finite() && grid.size > 0.) {
+        return Err("cell size has to be a positive number");
+    }
+    if !(grid.distance.is_finite() && grid.distance > 0.) {
+        return Err("cell distance has to be a positive number");
+    }
+    Ok(())
+}
+
+//Rejects rings that are too short, left open or have non-finite points, which geo can't work
+//with at all. Self-intersecting rings aren't caught here.
+fn validate_area(area: &MultiPolygon) -> Result<(), &'static str> {
+    for polygon in area {
+        for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+            if ring.0.len() < 4 {
+                return Err("polygon ring has fewer than four points");
+            }
+            if !ring.is_closed() {
+                return Err("polygon ring is not closed");
+            }
+            if ring.coords().any(|c| !c.x.is_finite() || !c.y.is_finite()) {
+                return Err("polygon has a non-finite coordinate");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sketch::{BLACK, WHITE};
+    use geo::{polygon, Area, Contains};
+
+    #[test]
+    fn save_load_roundtrip() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(
+                0,
+                geo::MultiPolygon::new(vec![polygon![
+                    (x: 0., y: 0.),
+                    (x: 0., y: 100.),
+                    (x: 100., y: 100.),
+                    (x: 100., y: 0.)
+                ]]),
+            )),
+        );
+        let grid = GridSettings {
+            kind: GridKind::HexPointy,
+            size: 40.,
+            distance: 1.5,
+            unit: "m".to_string(),
+            mode: GridMode::InsideOnly,
+        };
+        history.edit(
+            &mut map,
+            MapEdit::ChangeGrid(MapChangeGrid::new(grid.clone())),
+        );
+        let path = std::env::temp_dir().join("macromapper_roundtrip_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let (mut loaded, mut loaded_history) = load_map(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!path.with_extension("mmap.tmp").exists());
+        map.regenerate_hatching();
+        loaded.regenerate_hatching();
+        assert_eq!(loaded.grid_settings(), &grid);
+
+        let original = map.layers_iter().next().unwrap();
+        let layer = loaded.layers_iter().next().unwrap();
+        assert_eq!(original.name(), layer.name());
+        assert_eq!(original.style(), layer.style());
+        assert_eq!(
+            original.area().unsigned_area(),
+            layer.area().unsigned_area()
+        );
+        //regenerated derived data should match what the edit produced
+        assert_eq!(original, layer);
+
+        //history survives the roundtrip
+        assert!(loaded_history.can_undo());
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.grid_settings(), &GridSettings::default());
+        loaded_history.undo(&mut loaded);
+        assert_eq!(
+            loaded.layers_iter().next().unwrap().area().unsigned_area(),
+            0.
+        );
+    }
+
+    fn v1_document(area: Value) -> Value {
+        json!({
+            "version": 1,
+            "layers": [{
+                "name": "old",
+                "area": area,
+                "style": {
+                    "outline_thickness": 3.0,
+                    "outline_color": [0.0, 0.0, 0.0, 1.0],
+                    "hatch_thickness": 2.0,
+                    "hatch_color": [0.5, 0.5, 0.5, 1.0],
+                },
+            }],
+            "history": serde_json::to_value(Record::<MapEdit>::new()).unwrap(),
+        })
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let area = geo::MultiPolygon::new(vec![polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 50.),
+            (x: 50., y: 50.),
+            (x: 50., y: 0.)
+        ]]);
+        let document = v1_document(serde_json::to_value(&area).unwrap());
+        let (map, _) = parse_map(&document.to_string()).unwrap();
+        let layer = map.layers_iter().next().unwrap();
+        assert_eq!(layer.name(), "old");
+        assert_eq!(layer.area().unsigned_area(), 2500.);
+    }
+
+    #[test]
+    fn migrate_v2() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        history.edit(
+            &mut map,
+            MapEdit::AddLayer(MapAddLayer::new(1, Layer::new("added".to_string()))),
+        );
+        history.edit(
+            &mut map,
+            MapEdit::ToggleLayer(MapToggleLayer::new(1, LayerFlag::Locked)),
+        );
+        history.undo(&mut map);
+        let path = std::env::temp_dir().join("macromapper_v2_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let mut document: Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        //v2 layers had no flags, on the map or in the history
+        for pointer in [
+            "/map/layers/0",
+            "/map/layers/1",
+            "/map/history/entries/0/edit/AddLayer/layer",
+        ] {
+            let layer = document
+                .pointer_mut(pointer)
+                .and_then(Value::as_object_mut)
+                .unwrap();
+            layer.remove("visible").unwrap();
+            layer.remove("locked").unwrap();
+        }
+        document["header"]["version"] = json!(2);
+
+        let (mut loaded, mut loaded_history) = parse_map(&document.to_string()).unwrap();
+        assert!(loaded.layers_iter().all(|l| l.visible() && !l.locked()));
+        loaded_history.redo(&mut loaded);
+        assert!(loaded.layer(1).unwrap().locked());
+        loaded_history.undo(&mut loaded);
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.layer_count(), 1);
+    }
+
+    //Turns a current document back into the v3 layout, where layers only had a pattern
+    fn to_v3(value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                if let Some(hatch) = object.remove("hatch") {
+                    object.insert("pattern".to_string(), hatch["pattern"].clone());
+                }
+                if let Some(edit) = object.remove("HatchLayer") {
+                    object.insert("PatternLayer".to_string(), edit);
+                }
+                object.values_mut().for_each(to_v3);
+            }
+            Value::Array(array) => array.iter_mut().for_each(to_v3),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn migrate_v3() {
+        let mut map = Map::new();
+        map.append_layer();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let stipple = HatchStyle {
+            pattern: HatchPattern::Stipple,
+            ..HatchStyle::default()
+        };
+        history.edit(
+            &mut map,
+            MapEdit::HatchLayer(MapHatchLayer::new(0, stipple)),
+        );
+        history.edit(&mut map, MapEdit::RemoveLayer(MapRemoveLayer::new(1)));
+        let path = std::env::temp_dir().join("macromapper_v3_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let mut document: Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        to_v3(&mut document);
+        //v3 had no grid settings
+        document["map"].as_object_mut().unwrap().remove("grid");
+        document["header"]["version"] = json!(3);
+
+        let (mut loaded, mut loaded_history) = parse_map(&document.to_string()).unwrap();
+        assert_eq!(loaded.layers_iter().next().unwrap().hatch(), &stipple);
+        //the removed layer and the pattern edit come back from the history
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.layer_count(), 2);
+        assert_eq!(loaded.layer(1).unwrap().hatch(), &HatchStyle::default());
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.layer(0).unwrap().hatch(), &HatchStyle::default());
+    }
+
+    #[test]
+    fn migrate_v4() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let style = LayerStyle {
+            floor_color: BLACK,
+            outline_thickness: 5.,
+            ..LayerStyle::default()
+        };
+        history.edit(&mut map, MapEdit::StyleLayer(MapStyleLayer::new(0, style)));
+        let path = std::env::temp_dir().join("macromapper_v4_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let mut document: Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        //v4 styles had no floor color, on layers or in style edits
+        for pointer in [
+            "/map/layers/0/style",
+            "/map/history/entries/0/edit/StyleLayer/style",
+        ] {
+            let style = document
+                .pointer_mut(pointer)
+                .and_then(Value::as_object_mut)
+                .unwrap();
+            style.remove("floor_color").unwrap();
+        }
+        document["header"]["version"] = json!(4);
+
+        let (mut loaded, mut loaded_history) = parse_map(&document.to_string()).unwrap();
+        let layer = loaded.layer(0).unwrap();
+        assert_eq!(layer.style().outline_thickness, 5.);
+        assert_eq!(layer.style().floor_color, WHITE);
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.layer(0).unwrap().style(), &LayerStyle::default());
+    }
+
+    #[test]
+    fn migrate_v5() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let hex = GridSettings {
+            kind: GridKind::HexFlat,
+            ..GridSettings::default()
+        };
+        history.edit(
+            &mut map,
+            MapEdit::ChangeGrid(MapChangeGrid::new(hex.clone())),
+        );
+        let path = std::env::temp_dir().join("macromapper_v5_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let mut document: Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        //v5 only stored the grid's shape, both on the map and in grid edits
+        let map_data = document["map"].as_object_mut().unwrap();
+        let grid = map_data.remove("grid").unwrap();
+        map_data.insert("grid_kind".to_string(), grid["kind"].clone());
+        let edit = document
+            .pointer_mut("/map/history/entries/0/edit/ChangeGrid")
+            .and_then(Value::as_object_mut)
+            .unwrap();
+        let grid = edit.remove("grid").unwrap();
+        edit.insert("grid_kind".to_string(), grid["kind"].clone());
+        document["header"]["version"] = json!(5);
+
+        let (mut loaded, mut loaded_history) = parse_map(&document.to_string()).unwrap();
+        assert_eq!(loaded.grid_settings(), &hex);
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.grid_settings(), &GridSettings::default());
+    }
+
+    #[test]
+    fn migrate_v8() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let hex = GridSettings {
+            kind: GridKind::HexFlat,
+            ..GridSettings::default()
+        };
+        history.edit(
+            &mut map,
+            MapEdit::ChangeGrid(MapChangeGrid::new(hex.clone())),
+        );
+        let path = std::env::temp_dir().join("macromapper_v8_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let mut document: Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        //v8 grids had no mode, on the map or in grid edits
+        for pointer in ["/map/grid", "/map/history/entries/0/edit/ChangeGrid/grid"] {
+            let grid = document.pointer_mut(pointer).unwrap();
+            grid.as_object_mut().unwrap().remove("mode").unwrap();
+        }
+        document["header"]["version"] = json!(8);
+
+        let (mut loaded, mut loaded_history) = parse_map(&document.to_string()).unwrap();
+        assert_eq!(loaded.grid_settings(), &hex);
+        assert_eq!(loaded.grid_settings().mode, GridMode::Full);
+        loaded_history.undo(&mut loaded);
+        assert_eq!(loaded.grid_settings(), &GridSettings::default());
+    }
+
+    #[test]
+    fn move_and_reshape_roundtrip() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let square = polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 50.),
+            (x: 50., y: 50.),
+            (x: 50., y: 0.)
+        ];
+        let area = geo::MultiPolygon::new(vec![square.clone()]);
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, area.clone())));
+        history.edit(
+            &mut map,
+            MapEdit::Move(MapMove::new(0, area, geo::coord! {x: 100., y: 0.})),
+        );
+        let moved = map.layer(0).unwrap().area().0[0].clone();
+        let triangle = polygon![(x: 100., y: 0.), (x: 100., y: 50.), (x: 150., y: 0.)];
+        history.edit(
+            &mut map,
+            MapEdit::Reshape(MapReshape::new(0, moved, triangle)),
+        );
+        let path = std::env::temp_dir().join("macromapper_move_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let (mut loaded, mut loaded_history) = load_map(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.layer(0).unwrap().area().unsigned_area(), 1250.);
+        loaded_history.undo(&mut loaded);
+        assert!(loaded
+            .layer(0)
+            .unwrap()
+            .area()
+            .contains(&geo::Point::new(125., 25.)));
+        loaded_history.undo(&mut loaded);
+        let area = loaded.layer(0).unwrap().area();
+        assert_eq!(area.unsigned_area(), 2500.);
+        assert!(area.contains(&geo::Point::new(25., 25.)));
+    }
+
+    #[test]
+    fn load_errors() {
+        let future = json!({"header": {"format": FORMAT_NAME, "version": FILE_VERSION + 1}});
+        assert!(matches!(
+            parse_map(&future.to_string()),
+            Err(FileError::UnsupportedVersion(_))
+        ));
+
+        let foreign = json!({"header": {"format": "something else", "version": 1}});
+        assert!(matches!(
+            parse_map(&foreign.to_string()),
+            Err(FileError::NotAMap)
+        ));
+
+        let open_ring = json!([{
+            "exterior": [{"x": 0.0, "y": 0.0}, {"x": 0.0, "y": 1.0}, {"x": 1.0, "y": 1.0}],
+            "interiors": [],
+        }]);
+        let corrupt = v1_document(open_ring);
+        assert!(matches!(
+            parse_map(&corrupt.to_string()),
+            Err(FileError::InvalidGeometry { .. })
+        ));
+
+        let mut document = v1_document(json!([]));
+        for migration in MIGRATIONS {
+            document = migration(document).unwrap();
+        }
+        document["map"]["grid"]["size"] = json!(0.);
+        assert!(matches!(
+            parse_map(&document.to_string()),
+            Err(FileError::InvalidGrid(_))
+        ));
+    }
+
+    #[test]
+    fn history_errors() {
+        let mut map = Map::new();
+        map.append_layer();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let square = geo::MultiPolygon::new(vec![polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 50.),
+            (x: 50., y: 50.),
+            (x: 50., y: 0.)
+        ]]);
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(1, square)));
+        history.edit(&mut map, MapEdit::RemoveLayer(MapRemoveLayer::new(1)));
+        history.edit(
+            &mut map,
+            MapEdit::ChangeGrid(MapChangeGrid::new(GridSettings {
+                size: 60.,
+                ..GridSettings::default()
+            })),
+        );
+        //the grid change is left to be redone
+        history.undo(&mut map);
+        let path = std::env::temp_dir().join("macromapper_history_test.mmap");
+        save_map(&path, &map, &history).unwrap();
+        let document: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(parse_map(&document.to_string()).is_ok());
+
+        let corrupt = |pointer: &str, value: Value| {
+            let mut document = document.clone();
+            *document.pointer_mut(pointer).unwrap() = value;
+            match parse_map(&document.to_string()) {
+                Err(FileError::InvalidHistory { entry, .. }) => entry,
+                _ => panic!("{pointer} should be rejected"),
+            }
+        };
+        //the union was made while the removed layer was still there
+        assert_eq!(
+            corrupt("/map/history/entries/0/edit/Union/layer", json!(2)),
+            0
+        );
+        assert_eq!(
+            corrupt("/map/history/entries/1/edit/RemoveLayer/layer", json!(null)),
+            1
+        );
+        assert_eq!(
+            corrupt(
+                "/map/history/entries/2/edit/ChangeGrid/grid/size",
+                json!(0.)
+            ),
+            2
+        );
+        assert_eq!(corrupt("/map/history/index", json!(4)), 4);
+        let open_ring = json!([{"exterior": [{"x": 0.0, "y": 0.0}], "interiors": []}]);
+        assert_eq!(
+            corrupt("/map/history/entries/0/edit/Union/operator", open_ring),
+            0
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use geo::{coord, Coord, Rect};
+use serde::{Deserialize, Serialize};
+
+use crate::sketch::{Line, Sketch, LIGHTGRAY};
+
+//Everything the app needs to know about a grid's shape: how to draw it and where points snap to.
+//size is the distance between neighbouring cells, so every grid looks about as dense
+pub trait Grid {
+    //Grid lines covering rect
+    fn sketch(&self, rect: Rect<f32>) -> Sketch;
+    //Nearest point to snap to, on a grid with cells fraction times as big
+    fn snap(&self, point: Coord<f32>, fraction: f32) -> Coord<f32>;
+}
+
+//Which grid a map uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GridKind {
+    #[default]
+    Square,
+    HexFlat,
+    HexPointy,
+    Iso,
+}
+
+impl GridKind {
+    pub const ALL: [GridKind; 4] = [
+        GridKind::Square,
+        GridKind::HexFlat,
+        GridKind::HexPointy,
+        GridKind::Iso,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridKind::Square => "Square",
+            GridKind::HexFlat => "Hex (flat top)",
+            GridKind::HexPointy => "Hex (pointy top)",
+            GridKind::Iso => "Isometric",
+        }
+    }
+    pub fn grid(&self, size: f32) -> Box<dyn Grid> {
+        match self {
+            GridKind::Square => Box::new(SquareGrid { size }),
+            GridKind::HexFlat => Box::new(HexGrid { size, flat: true }),
+            GridKind::HexPointy => Box::new(HexGrid { size, flat: false }),
+            GridKind::Iso => Box::new(IsoGrid { size }),
+        }
+    }
+}
+
+//A map's grid: its shape, how many world units a cell is, how far a cell is in the real world
+//and how much of it is drawn. Exports start from the map's mode but can pick another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridSettings {
+    pub kind: GridKind,
+    pub size: f32,
+    pub distance: f32,
+    pub unit: String,
+    pub mode: GridMode,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        GridSettings {
+            kind: GridKind::Square,
+            size: 50.,
+            distance: 5.,
+            unit: "ft".to_string(),
+            mode: GridMode::Full,
+        }
+    }
+}
+
+impl GridSettings {
+    //The usual scales, anything else is custom
+    pub const PRESETS: [(f32, &'static str); 3] = [(5., "ft"), (10., "ft"), (1.5, "m")];
+    pub fn grid(&self) -> Box<dyn Grid> {
+        self.kind.grid(self.size)
+    }
+    //A world-space length in real units, e.g. "12.5 ft"
+    pub fn measure(&self, length: f32) -> String {
+        let real = length / self.size * self.distance;
+        //two decimals at most, without trailing zeroes
+        let text = format!("{real:.2}");
+        let text = text.trim_end_matches('0').trim_end_matches('.');
+        format!("{text} {}", self.unit)
+    }
+}
+
+//How much of the grid to draw: all of it, only inside the visible layers' areas, or none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GridMode {
+    #[default]
+    Full,
+    InsideOnly,
+    Hidden,
+}
+
+impl GridMode {
+    pub const ALL: [GridMode; 3] = [GridMode::Full, GridMode::InsideOnly, GridMode::Hidden];
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridMode::Full => "Full",
+            GridMode::InsideOnly => "Inside rooms",
+            GridMode::Hidden => "Hidden",
+        }
+    }
+}
+
+pub struct SquareGrid {
+    pub size: f32,
+}
+
+impl Grid for SquareGrid {
+    fn sketch(&self, rect: Rect<f32>) -> Sketch {
+        let mut grid = Sketch::new(1., LIGHTGRAY);
+        let (min, max, size) = (rect.min(), rect.max(), self.size);
+        for i in (min.x / size).ceil() as i32..=(max.x / size).floor() as i32 {
+            grid.add(Line::new(i as f32 * size, min.y, i as f32 * size, max.y));
+        }
+        for i in (min.y / size).ceil() as i32..=(max.y / size).floor() as i32 {
+            grid.add(Line::new(min.x, i as f32 * size, max.x, i as f32 * size));
+        }
+        grid
+    }
+    fn snap(&self, point: Coord<f32>, fraction: f32) -> Coord<f32> {
+        let step = self.size * fraction;
+        coord! {x: (point.x / step).round() * step, y: (point.y / step).round() * step}
+    }
+}
+
+//Hexes size apart, one of them centred on the origin. Flat topped hexes are the pointy
+//ones mirrored across y = x, so all of the work is done pointy side up.
+pub struct HexGrid {
+    pub size: f32,
+    pub flat: bool,
+}
+
+impl HexGrid {
+    fn mirror(&self, c: Coord<f32>) -> Coord<f32> {
+        if self.flat {
+            coord! {x: c.y, y: c.x}
+        } else {
+            c
+        }
+    }
+    //distance from a hex's centre to its corners
+    fn radius(size: f32) -> f32 {
+        size / 3f32.sqrt()
+    }
+    //centre of the hex at axial coordinates q, r
+    fn center(size: f32, q: i32, r: i32) -> Coord<f32> {
+        coord! {x: size * (q as f32 + r as f32 / 2.), y: Self::radius(size) * 1.5 * r as f32}
+    }
+    fn corner(size: f32, center: Coord<f32>, i: i32) -> Coord<f32> {
+        let angle = (30. + 60. * i as f32).to_radians();
+        center + coord! {x: angle.cos(), y: angle.sin()} * Self::radius(size)
+    }
+    //hex containing point, by rounding cube coordinates
+    fn containing(size: f32, point: Coord<f32>) -> (i32, i32) {
+        let radius = Self::radius(size);
+        let q = (3f32.sqrt() / 3. * point.x - point.y / 3.) / radius;
+        let r = (2. / 3. * point.y) / radius;
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+        (rq as i32, rr as i32)
+    }
+}
+
+impl Grid for HexGrid {
+    fn sketch(&self, rect: Rect<f32>) -> Sketch {
+        let mut grid = Sketch::new(1., LIGHTGRAY);
+        let size = self.size;
+        let rect = Rect::new(self.mirror(rect.min()), self.mirror(rect.max()));
+        let row_height = Self::radius(size) * 1.5;
+        //one extra hex all around, since each hex only draws half of its edges
+        let rows = (rect.min().y / row_height).floor() as i32 - 1
+            ..=(rect.max().y / row_height).ceil() as i32 + 1;
+        for r in rows {
+            let shift = r as f32 / 2.;
+            let columns = (rect.min().x / size - shift).floor() as i32 - 1
+                ..=(rect.max().x / size - shift).ceil() as i32 + 1;
+            for q in columns {
+                let center = Self::center(size, q, r);
+                //the right and both upper edges, the other three belong to the neighbours
+                for i in -1..2 {
+                    let (a, b) = (
+                        Self::corner(size, center, i),
+                        Self::corner(size, center, i + 1),
+                    );
+                    let (a, b) = (self.mirror(a), self.mirror(b));
+                    grid.add(Line::new(a.x, a.y, b.x, b.y));
+                }
+            }
+        }
+        grid
+    }
+    //Centres and corners together make a triangular lattice, and the nearest lattice point
+    //is always one of the corners of the containing hex or its centre
+    fn snap(&self, point: Coord<f32>, fraction: f32) -> Coord<f32> {
+        let size = self.size * fraction;
+        let point = self.mirror(point);
+        let (q, r) = Self::containing(size, point);
+        let center = Self::center(size, q, r);
+        let nearest = (0..6)
+            .map(|i| Self::corner(size, center, i))
+            .chain(std::iter::once(center))
+            .min_by(|a, b| distance(*a, point).total_cmp(&distance(*b, point)))
+            .unwrap_or(center);
+        self.mirror(nearest)
+    }
+}
+
+//Diamonds twice as wide as they are tall, like the floor tiles in an isometric game.
+//Corners sit on the lattice spanned by (size, size/2) and (size, -size/2)
+pub struct IsoGrid {
+    pub size: f32,
+}
+
+impl Grid for IsoGrid {
+    fn sketch(&self, rect: Rect<f32>) -> Sketch {
+        let mut grid = Sketch::new(1., LIGHTGRAY);
+        let (min, max, size) = (rect.min(), rect.max(), self.size);
+        for slope in [0.5f32, -0.5] {
+            //lines are y = slope * x + k * size
+            let offsets = [
+                min,
+                max,
+                coord! {x: min.x, y: max.y},
+                coord! {x: max.x, y: min.y},
+            ]
+            .map(|c| (c.y - slope * c.x) / size);
+            let lowest = offsets.iter().copied().fold(f32::MAX, f32::min).ceil() as i32;
+            let highest = offsets.iter().copied().fold(f32::MIN, f32::max).floor() as i32;
+            for k in lowest..=highest {
+                let b = k as f32 * size;
+                //the part of the line with y inside the rect, then inside its x range too
+                let (x1, x2) = ((min.y - b) / slope, (max.y - b) / slope);
+                let (start, end) = (x1.min(x2).max(min.x), x1.max(x2).min(max.x));
+                if start < end {
+                    grid.add(Line::new(start, slope * start + b, end, slope * end + b));
+                }
+            }
+        }
+        grid
+    }
+    fn snap(&self, point: Coord<f32>, fraction: f32) -> Coord<f32> {
+        let size = self.size * fraction;
+        let at = |i: f32, j: f32| coord! {x: size * (i + j), y: size / 2. * (i - j)};
+        //lattice coordinates, the nearest corner is one of the four around them
+        let i = (point.x / size + 2. * point.y / size) / 2.;
+        let j = (point.x / size - 2. * point.y / size) / 2.;
+        let (i, j) = (i.floor(), j.floor());
+        [at(i, j), at(i + 1., j), at(i, j + 1.), at(i + 1., j + 1.)]
+            .into_iter()
+            .min_by(|a, b| distance(*a, point).total_cmp(&distance(*b, point)))
+            .unwrap_or(point)
+    }
+}
+
+fn distance(a: Coord<f32>, b: Coord<f32>) -> f32 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view() -> Rect<f32> {
+        Rect::new(coord! {x: -130., y: -70.}, coord! {x: 240., y: 180.})
+    }
+
+    #[test]
+    fn snapping_is_idempotent() {
+        for kind in GridKind::ALL {
+            let grid = kind.grid(50.);
+            for fraction in [1., 0.5, 1. / 3.] {
+                for (x, y) in [(0., 0.), (13., -7.), (-88.8, 41.2), (160., 97.)] {
+                    let snapped = grid.snap(coord! {x: x, y: y}, fraction);
+                    //never further than a cell away
+                    assert!(
+                        distance(snapped, coord! {x: x, y: y}) <= 50. * fraction,
+                        "{kind:?}"
+                    );
+                    let again = grid.snap(snapped, fraction);
+                    assert!(distance(snapped, again) < 1e-3, "{kind:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn snap_points_lie_on_grid_lines() {
+        //at full size every snap point is on the drawn grid, except hex centres
+        for kind in GridKind::ALL {
+            let grid = kind.grid(50.);
+            let sketch = grid.sketch(view());
+            assert!(!sketch.lines().is_empty());
+            for (x, y) in [(13., -7.), (-60., 41.2), (120., 97.)] {
+                let snapped = grid.snap(coord! {x: x, y: y}, 1.);
+                let on_line = sketch.lines().iter().any(|l| {
+                    let line = geo::Line::new(l.start(), l.end());
+                    geo::EuclideanDistance::euclidean_distance(&line, &geo::Point(snapped)) < 1e-2
+                });
+                let is_center = matches!(kind, GridKind::HexFlat | GridKind::HexPointy)
+                    && sketch
+                        .lines()
+                        .iter()
+                        .all(|l| distance(l.start(), snapped) > 1.);
+                assert!(on_line || is_center, "{kind:?} {snapped:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn measurements() {
+        let settings = GridSettings::default();
+        assert_eq!(settings.measure(50.), "5 ft");
+        assert_eq!(settings.measure(125.), "12.5 ft");
+        let metric = GridSettings {
+            size: 40.,
+            distance: 1.5,
+            unit: "m".to_string(),
+            ..GridSettings::default()
+        };
+        assert_eq!(metric.measure(120.), "4.5 m");
+        assert_eq!(metric.measure(0.), "0 m");
+    }
+
+    #[test]
+    fn hexes_are_size_apart() {
+        for flat in [true, false] {
+            let grid = HexGrid { size: 50., flat };
+            //from a centre, the next centre over is one size away
+            let center = grid.snap(coord! {x: 0., y: 0.}, 1.);
+            let step = if flat {
+                coord! {x: 0., y: 50.}
+            } else {
+                coord! {x: 50., y: 0.}
+            };
+            let next = grid.snap(center + step, 1.);
+            assert!((distance(center, next) - 50.).abs() < 1e-3);
+            //every edge is a side of a hex with the right radius
+            for line in grid.sketch(view()).lines() {
+                let length = distance(line.start(), line.end());
+                assert!((length - HexGrid::radius(50.)).abs() < 1e-3);
+            }
+        }
+    }
+}
@@ -0,0 +1,824 @@
+use crate::map::Map;
+use crate::sketch::Line;
+use fast_poisson::Poisson2D;
+use geo::line_intersection::line_intersection;
+use geo::*;
+use serde::{Deserialize, Serialize};
+use voronator::VoronoiDiagram;
+
+//Hatching is the slowest part of an edit by far, so the app hands it to a worker thread.
+//Layers keep their old hatching until the new one arrives, and results for an area that has
+//changed again since the request are thrown away by Map::set_hatching.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Hatcher {
+    jobs: std::sync::mpsc::Sender<HatchJob>,
+    results: std::sync::mpsc::Receiver<HatchJob>,
+    //last revision sent to the worker for each layer id
+    requested: std::collections::HashMap<u64, u64>,
+}
+
+//Threads aren't available in the browser, so the web build hatches in place
+#[cfg(target_arch = "wasm32")]
+pub struct Hatcher;
+
+//A request going to the worker, and later its result coming back with the hatching filled in
+#[cfg(not(target_arch = "wasm32"))]
+struct HatchJob {
+    layer: u64,
+    revision: u64,
+    area: MultiPolygon,
+    style: HatchStyle,
+    tiles: Vec<TileCoord>,
+    hatching: Vec<(TileCoord, TileHatching)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Hatcher {
+    pub fn new() -> Self {
+        let (jobs, worker_jobs) = std::sync::mpsc::channel::<HatchJob>();
+        let (worker_results, results) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            //ends once the Hatcher is dropped
+            while let Ok(job) = worker_jobs.recv() {
+                //a quick series of edits queues up several jobs for one layer. Dirty tiles
+                //are only cleared when results arrive, so the newest covers all the others
+                let mut queue = vec![job];
+                queue.extend(worker_jobs.try_iter());
+                let newest = queue
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, job)| !queue[i + 1..].iter().any(|j| j.layer == job.layer))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                for (i, mut job) in queue.into_iter().enumerate() {
+                    if newest.contains(&i) {
+                        job.hatching = hatch_tiles(&job.area, &job.style, &job.tiles);
+                        if worker_results.send(job).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        Hatcher {
+            jobs,
+            results,
+            requested: Default::default(),
+        }
+    }
+    //Call once per frame: applies finished hatching and sends off layers that need new hatching
+    pub fn update(&mut self, map: &mut Map) {
+        for job in self.results.try_iter() {
+            //applied or dropped, it's answered. Forgetting it lets a layer brought back by
+            //undo ask again
+            if self.requested.get(&job.layer) == Some(&job.revision) {
+                self.requested.remove(&job.layer);
+            }
+            map.set_hatching(job.layer, job.revision, job.hatching);
+        }
+        self.requested
+            .retain(|id, _| map.layers_iter().any(|l| l.id() == *id));
+        for layer in map.layers_iter().filter(|l| l.hatching_stale()) {
+            if self.requested.get(&layer.id()) == Some(&layer.revision()) {
+                continue;
+            }
+            self.requested.insert(layer.id(), layer.revision());
+            let job = HatchJob {
+                layer: layer.id(),
+                revision: layer.revision(),
+                area: layer.area().clone(),
+                style: *layer.hatch(),
+                tiles: layer.dirty_tiles().collect(),
+                hatching: vec![],
+            };
+            //the worker only stops if it panicked, and then there's nothing left to do
+            let _ = self.jobs.send(job);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Hatcher {
+    pub fn new() -> Self {
+        Hatcher
+    }
+    pub fn update(&mut self, map: &mut Map) {
+        map.regenerate_hatching();
+    }
+}
+
+impl Default for Hatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//Hatching is generated in fixed world-space tiles, each seeded from its own coordinates,
+//so an edit only has to redo the tiles around it and the rest of the pattern never moves
+pub const HATCH_TILE_SIZE: f64 = 200.;
+
+pub type TileCoord = (i32, i32);
+
+//What gets drawn in the band around a layer's area
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HatchPattern {
+    #[default]
+    Lines,
+    CrossHatch,
+    Stipple,
+    Solid,
+    None,
+}
+
+impl HatchPattern {
+    pub const ALL: [HatchPattern; 5] = [
+        HatchPattern::Lines,
+        HatchPattern::CrossHatch,
+        HatchPattern::Stipple,
+        HatchPattern::Solid,
+        HatchPattern::None,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            HatchPattern::Lines => "Lines",
+            HatchPattern::CrossHatch => "Cross-hatch",
+            HatchPattern::Stipple => "Stipple",
+            HatchPattern::Solid => "Solid",
+            HatchPattern::None => "None",
+        }
+    }
+}
+
+//Everything that decides the shape of a layer's hatching. Thickness and colour are part of
+//LayerStyle instead, since they can change without regenerating anything.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HatchStyle {
+    pub pattern: HatchPattern,
+    //spacing of the cells, and so the size of each patch of hatching
+    pub radius: f64,
+    //width of the band around the area
+    pub offset: f64,
+    //strokes on each side of a cell's centre
+    pub hatch_count: i32,
+    pub seed: u64,
+    //shorter strokes are left out
+    pub min_length: f64,
+}
+
+impl Default for HatchStyle {
+    fn default() -> Self {
+        HatchStyle {
+            pattern: HatchPattern::Lines,
+            radius: 20.,
+            offset: 50.,
+            hatch_count: 10,
+            seed: 0x5EED,
+            min_length: 7.,
+        }
+    }
+}
+
+impl HatchStyle {
+    //How far outside a tile a change to the area can affect the tile's hatching. Cells reach a
+    //couple of radii past their site and are kept up to 3/4 of the offset away from the area.
+    pub fn reach(&self) -> f64 {
+        self.radius * 3. + self.offset * 0.75
+    }
+    fn band(&self) -> f64 {
+        self.offset * 0.75
+    }
+}
+
+//Strokes and shaded polygons for one tile. Stipple dots are zero-length strokes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TileHatching {
+    pub lines: Vec<Line>,
+    pub shading: Vec<Polygon<f32>>,
+}
+
+impl TileHatching {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.shading.is_empty()
+    }
+}
+
+//Every tile whose hatching could depend on the area inside rect
+pub fn tiles_near(rect: Rect, style: &HatchStyle) -> impl Iterator<Item = TileCoord> {
+    let tile = |v: f64| (v / HATCH_TILE_SIZE).floor() as i32;
+    let reach = style.reach();
+    let (min_x, min_y) = (tile(rect.min().x - reach), tile(rect.min().y - reach));
+    let (max_x, max_y) = (tile(rect.max().x + reach), tile(rect.max().y + reach));
+    (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+}
+
+//Hatching for a whole area, as if every tile was dirty
+pub fn hatch_area(area: &MultiPolygon, style: &HatchStyle) -> TileHatching {
+    let mut hatching = TileHatching::default();
+    let Some(rect) = area.bounding_rect() else {
+        return hatching;
+    };
+    let tiles: Vec<_> = tiles_near(rect, style).collect();
+    for (_, tile) in hatch_tiles(area, style, &tiles) {
+        hatching.lines.extend(tile.lines);
+        hatching.shading.extend(tile.shading);
+    }
+    hatching
+}
+
+pub fn hatch_tiles(
+    area: &MultiPolygon,
+    style: &HatchStyle,
+    tiles: &[TileCoord],
+) -> Vec<(TileCoord, TileHatching)> {
+    //neighbouring tiles share most of their points
+    let mut points = std::collections::HashMap::new();
+    tiles
+        .iter()
+        .map(|&tile| {
+            let hatching = match style.pattern {
+                HatchPattern::None => TileHatching::default(),
+                HatchPattern::Stipple => TileHatching {
+                    lines: tile_stipple(area, style, tile),
+                    shading: vec![],
+                },
+                HatchPattern::Solid => TileHatching {
+                    lines: vec![],
+                    shading: tile_cells(area, style, tile, &mut points)
+                        .into_iter()
+                        .map(|(_, cell)| cell.map_coords(|c| coord! {x: c.x as f32, y: c.y as f32}))
+                        .collect(),
+                },
+                HatchPattern::Lines => TileHatching {
+                    lines: hatch_cells(tile_cells(area, style, tile, &mut points), style, &[0.]),
+                    shading: vec![],
+                },
+                HatchPattern::CrossHatch => TileHatching {
+                    lines: hatch_cells(
+                        tile_cells(area, style, tile, &mut points),
+                        style,
+                        &[0., 90.],
+                    ),
+                    shading: vec![],
+                },
+            };
+            (tile, hatching)
+        })
+        .collect()
+}
+
+fn tile_origin(tile: TileCoord) -> Coord {
+    coord! {x: tile.0 as f64 * HATCH_TILE_SIZE, y: tile.1 as f64 * HATCH_TILE_SIZE}
+}
+
+//The hatching has to look the same on every build, and DefaultHasher is allowed to change
+//between Rust releases, so seeds are mixed with splitmix64's finaliser instead
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn stable_hash(values: impl IntoIterator<Item = u64>) -> u64 {
+    values.into_iter().fold(0, |hash, v| mix(hash ^ v))
+}
+
+fn tile_seed(tile: TileCoord, seed: u64, salt: &str) -> u64 {
+    let values = [tile.0 as u32 as u64, tile.1 as u32 as u64, seed];
+    stable_hash(values.into_iter().chain(salt.bytes().map(u64::from)))
+}
+
+fn tile_points(tile: TileCoord, radius: f64, seed: u64) -> Vec<(f64, f64)> {
+    let origin = tile_origin(tile);
+    Poisson2D::new()
+        .with_seed(seed)
+        .with_dimensions([HATCH_TILE_SIZE, HATCH_TILE_SIZE], radius)
+        .iter()
+        .map(|point| (point[0] + origin.x, point[1] + origin.y))
+        .collect()
+}
+
+//The cells in the band around the area, clipped to it, each with the site it grew from.
+//The tile owns the cells whose sites are inside it. Sites from the 8 neighbours are
+//included so those cells come out the same shape no matter which tile is being hatched.
+fn tile_cells(
+    area: &MultiPolygon,
+    style: &HatchStyle,
+    tile: TileCoord,
+    cache: &mut std::collections::HashMap<TileCoord, Vec<(f64, f64)>>,
+) -> Vec<(Point, Polygon)> {
+    let origin = tile_origin(tile);
+    let mut sites = |tile: TileCoord| {
+        cache
+            .entry(tile)
+            .or_insert_with(|| {
+                tile_points(tile, style.radius, tile_seed(tile, style.seed, "cells"))
+            })
+            .clone()
+    };
+    let mut points = sites(tile);
+    let owned = points.len();
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            if (dx, dy) != (0, 0) {
+                points.extend(sites((tile.0 + dx, tile.1 + dy)));
+            }
+        }
+    }
+    VoronoiDiagram::<voronator::delaunator::Point>::from_tuple(
+        &(origin.x - HATCH_TILE_SIZE, origin.y - HATCH_TILE_SIZE),
+        &(
+            origin.x + HATCH_TILE_SIZE * 2.,
+            origin.y + HATCH_TILE_SIZE * 2.,
+        ),
+        &points,
+    )
+    .expect("points should give a valid voronoi diagram")
+    .cells()
+    .iter()
+    .zip(&points[..owned])
+    .map(|(polygon, &site)| -> (Point, Polygon<f64>) {
+        let polygon = Polygon::new(
+            LineString::from(
+                polygon
+                    .points()
+                    .iter()
+                    .map(|p| (p.x, p.y))
+                    .collect::<Vec<_>>(),
+            ),
+            vec![],
+        );
+        (site.into(), polygon)
+    })
+    .filter(|(_, polygon)| {
+        let centroid = polygon
+            .centroid()
+            .expect("all polygons should have a centroid");
+        !area.contains(polygon) && area.euclidean_distance(&centroid) <= style.band()
+    })
+    .map(|(site, polygon)| {
+        let clipped = MultiPolygon::from(polygon)
+            .boolean_op(area, OpType::Difference)
+            .into_iter()
+            .next()
+            .expect("should be exactly one");
+        (site, clipped)
+    })
+    .collect()
+}
+
+//Cells where the kept strokes cover less than this share of the cell get hatched again
+const MIN_COVERAGE: f64 = 0.5;
+
+//Parallel strokes across each cell, once for every angle in layers
+fn hatch_cells(cells: Vec<(Point, Polygon)>, style: &HatchStyle, layers: &[f64]) -> Vec<Line> {
+    let mut lines = vec![];
+    let (radius, hatch_count) = (style.radius, style.hatch_count.max(1));
+    let spacing = radius * 4. / hatch_count as f64;
+    let base = stroke_set(spacing, hatch_count, radius * 2.);
+    //Hatches are placed around the site rather than the clipped cell's centroid,
+    //which would shift slightly whenever the geometry around it is rebuilt
+    for (center, polygon) in cells {
+        let rot = rotation_for(center) as f64;
+        let mut hatches = cell_strokes(&polygon, &base, center, rot, layers, style.min_length);
+        //Short strokes look bad, so they're dropped. When a large cell is cut to a thin
+        //sliver by the map geometry and the hatches run across it, that drops almost all of
+        //them and leaves a blank gap in the band. Those cells are hatched again along their
+        //long side, closer together, so that a few strokes always fit.
+        if coverage(&hatches, spacing, &polygon) < MIN_COVERAGE {
+            if let Some((middle, angle, width, length)) = long_axis(&polygon) {
+                let dense = spacing.min(width / 3.);
+                let count = (width / 2. / dense).ceil() as i32;
+                let fallback = cell_strokes(
+                    &polygon,
+                    &stroke_set(dense, count, length),
+                    middle,
+                    angle,
+                    layers,
+                    style.min_length,
+                );
+                if coverage(&fallback, dense, &polygon) > coverage(&hatches, spacing, &polygon) {
+                    hatches = fallback;
+                }
+            }
+        }
+        for line in hatches {
+            lines.push(Line::from_geo(line));
+        }
+    }
+    lines
+}
+
+//Vertical strokes spaced evenly around the origin
+fn stroke_set(spacing: f64, count: i32, half_length: f64) -> Vec<geo::Line> {
+    (-count..=count)
+        .map(|i| {
+            let x = spacing * i as f64;
+            geo::Line::new(
+                coord! {x: x, y: -half_length},
+                coord! {x: x, y: half_length},
+            )
+        })
+        .collect()
+}
+
+//The stroke set moved to center and rotated by rot, once for every angle in layers,
+//clipped to the polygon and without the strokes shorter than min_length
+fn cell_strokes(
+    polygon: &Polygon,
+    base: &[geo::Line],
+    center: Point,
+    rot: f64,
+    layers: &[f64],
+    min_length: f64,
+) -> Vec<geo::Line> {
+    layers
+        .iter()
+        .flat_map(|angle| {
+            base.iter().map(move |hatch| {
+                let mut hatch = *hatch;
+                hatch.translate_mut(center.x(), center.y());
+                hatch.rotate_around_point_mut(rot + angle, center);
+                hatch
+            })
+        })
+        .filter_map(|hatch| {
+            let new_points = polygon
+                .lines_iter()
+                .filter_map(|line| line_intersection(hatch, line))
+                .filter_map(|intersection| match intersection {
+                    LineIntersection::SinglePoint {
+                        intersection,
+                        is_proper: _,
+                    } => Some(intersection),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if new_points.len() != 2 {
+                None
+            } else {
+                Some(geo::Line {
+                    start: new_points[0],
+                    end: new_points[1],
+                })
+            }
+        })
+        .filter(|line| line.euclidean_length() > min_length)
+        .collect()
+}
+
+//Rough share of the cell covered by strokes, counting each one as a strip spacing wide
+fn coverage(hatches: &[geo::Line], spacing: f64, polygon: &Polygon) -> f64 {
+    let area = polygon.unsigned_area();
+    if area <= 0. {
+        return 1.;
+    }
+    let length: f64 = hatches.iter().map(|l| l.euclidean_length()).sum();
+    length * spacing / area
+}
+
+//Middle of the cell's bounding box, the rotation that makes strokes run along its
+//longest side, and its width and length
+fn long_axis(polygon: &Polygon) -> Option<(Point, f64, f64, f64)> {
+    let rect = polygon.minimum_rotated_rect()?;
+    let c = &rect.exterior().0;
+    let (a, b) = (c[1] - c[0], c[2] - c[1]);
+    let (long, short) = if a.x.hypot(a.y) >= b.x.hypot(b.y) {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    //unrotated strokes point along y
+    let angle = long.y.atan2(long.x).to_degrees() - 90.;
+    let middle = rect.centroid()?;
+    Some((middle, angle, short.x.hypot(short.y), long.x.hypot(long.y)))
+}
+
+//Dots that thin out away from the area. Every dot belongs to the tile it was sampled in,
+//so unlike the cells no neighbours are needed.
+fn tile_stipple(area: &MultiPolygon, style: &HatchStyle, tile: TileCoord) -> Vec<Line> {
+    let band = style.band();
+    tile_points(
+        tile,
+        style.radius / 4.,
+        tile_seed(tile, style.seed, "stipple"),
+    )
+    .into_iter()
+    .map(Point::from)
+    .filter(|point| {
+        if area.contains(point) {
+            return false;
+        }
+        let distance = area.euclidean_distance(point);
+        distance <= band && dot_chance(*point) < 1. - distance / band
+    })
+    .map(|point| {
+        let (x, y) = (point.x() as f32, point.y() as f32);
+        Line::new(x, y, x, y)
+    })
+    .collect()
+}
+
+//A stable number from 0 to 1 for a point
+fn dot_chance(point: Point) -> f64 {
+    (stable_hash([point.x().to_bits(), point.y().to_bits()]) % 1000) as f64 / 1000.
+}
+
+//Hatch direction for a cell, in degrees. Seeded from the cell's position so that
+//the pattern is the same every time the hatching is regenerated.
+fn rotation_for(center: Point) -> u64 {
+    stable_hash([(center.x() + center.y()).to_bits()]) % 180
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::*;
+    use undo::Record;
+
+    fn square(size: f64) -> MultiPolygon {
+        room(0., 0., size)
+    }
+
+    fn room(x: f64, y: f64, size: f64) -> MultiPolygon {
+        MultiPolygon::new(vec![polygon![
+            (x: x, y: y),
+            (x: x, y: y + size),
+            (x: x + size, y: y + size),
+            (x: x + size, y: y)
+        ]])
+    }
+
+    //Boolean ops can start rings at different vertices, so lines are compared
+    //without direction and with a little rounding
+    fn normalized(lines: &[Line]) -> Vec<[i64; 4]> {
+        let mut lines: Vec<_> = lines
+            .iter()
+            .map(|l| {
+                let round =
+                    |c: Coord<f32>| ((c.x * 100.).round() as i64, (c.y * 100.).round() as i64);
+                let (a, b) = (round(l.start()), round(l.end()));
+                let (a, b) = if a <= b { (a, b) } else { (b, a) };
+                [a.0, a.1, b.0, b.1]
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn seeds_are_pinned() {
+        //splitmix64's first output for a zero seed
+        assert_eq!(mix(0), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(tile_seed((1, -2), 7, "cells"), 14136618297049213385);
+        assert_eq!(dot_chance(Point::new(0., 0.)), 0.055);
+    }
+
+    #[test]
+    fn stale_results_are_dropped() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, square(100.))));
+        let layer = map.layers_iter().next().unwrap();
+        let (id, old_revision) = (layer.id(), layer.revision());
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, square(200.))));
+
+        //hatching for the first square arrives after the second edit
+        let tiles: Vec<_> = tiles_near(
+            square(100.).bounding_rect().unwrap(),
+            &HatchStyle::default(),
+        )
+        .collect();
+        map.set_hatching(
+            id,
+            old_revision,
+            hatch_tiles(&square(100.), &HatchStyle::default(), &tiles),
+        );
+        let layer = map.layers_iter().next().unwrap();
+        assert!(layer.hatching_stale());
+        assert!(layer.hatching().lines().is_empty());
+
+        map.regenerate_hatching();
+        let layer = map.layers_iter().next().unwrap();
+        assert!(!layer.hatching_stale());
+        assert_eq!(
+            layer.hatching().lines(),
+            hatch_area(&square(200.), &HatchStyle::default()).lines
+        );
+    }
+
+    #[test]
+    fn hatcher_finishes_in_background() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let mut hatcher = Hatcher::new();
+        for size in [100., 150., 200.] {
+            history.edit(&mut map, MapEdit::Union(MapUnion::new(0, square(size))));
+            hatcher.update(&mut map);
+        }
+        for _ in 0..1000 {
+            hatcher.update(&mut map);
+            if !map.layers_iter().any(|l| l.hatching_stale()) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let layer = map.layers_iter().next().unwrap();
+        assert!(!layer.hatching_stale());
+        assert_eq!(
+            layer.hatching().lines(),
+            hatch_area(&square(200.), &HatchStyle::default()).lines
+        );
+    }
+
+    #[test]
+    fn undone_delete_gets_hatched() {
+        let mut map = Map::new();
+        map.append_layer();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let mut hatcher = Hatcher::new();
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(1, square(100.))));
+        hatcher.update(&mut map);
+        //the result comes back while the layer is gone
+        history.edit(&mut map, MapEdit::RemoveLayer(MapRemoveLayer::new(1)));
+        for _ in 0..1000 {
+            hatcher.update(&mut map);
+            if hatcher.requested.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        history.undo(&mut map);
+        for _ in 0..1000 {
+            hatcher.update(&mut map);
+            if !map.layers_iter().any(|l| l.hatching_stale()) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let layer = map.layers_iter().nth(1).unwrap();
+        assert!(!layer.hatching_stale());
+        assert_eq!(
+            layer.hatching().lines(),
+            hatch_area(&square(100.), &HatchStyle::default()).lines
+        );
+    }
+
+    #[test]
+    fn edits_only_touch_nearby_tiles() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(0, room(0., 0., 100.))),
+        );
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(0, room(2000., 0., 100.))),
+        );
+        map.regenerate_hatching();
+        let far_tiles = |map: &Map| {
+            let layer = map.layers_iter().next().unwrap();
+            layer
+                .hatch_tiles()
+                .filter(|(tile, _)| tile.0 >= 8)
+                .map(|(tile, hatching)| (tile, hatching.clone()))
+                .collect::<Vec<_>>()
+        };
+        let before = far_tiles(&map);
+        assert!(!before.is_empty());
+
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(0, room(100., 0., 50.))),
+        );
+        let layer = map.layers_iter().next().unwrap();
+        assert!(layer.dirty_tiles().all(|tile| tile.0 < 8));
+        map.regenerate_hatching();
+        //the room far away keeps exactly the same hatching
+        assert_eq!(far_tiles(&map), before);
+
+        //and patching tiles gives the same result as hatching everything again
+        let layer = map.layers_iter().next().unwrap();
+        assert_eq!(
+            normalized(layer.hatching().lines()),
+            normalized(&hatch_area(layer.area(), layer.hatch()).lines)
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        let area = square(100.);
+        let hatch = |pattern| {
+            hatch_area(
+                &area,
+                &HatchStyle {
+                    pattern,
+                    ..HatchStyle::default()
+                },
+            )
+        };
+        let lines = hatch(HatchPattern::Lines);
+        let cross = hatch(HatchPattern::CrossHatch);
+        assert!(lines.shading.is_empty() && cross.shading.is_empty());
+        assert!(cross.lines.len() > lines.lines.len());
+
+        //dots are zero-length strokes outside the area, within the band
+        let stipple = hatch(HatchPattern::Stipple);
+        assert!(!stipple.lines.is_empty());
+        for dot in &stipple.lines {
+            assert_eq!(dot.start(), dot.end());
+            let point = Point::new(dot.start().x as f64, dot.start().y as f64);
+            assert!(!area.contains(&point));
+            assert!(area.euclidean_distance(&point) <= HatchStyle::default().offset);
+        }
+
+        //solid shading covers the same cells the lines are drawn in
+        let solid = hatch(HatchPattern::Solid);
+        assert!(solid.lines.is_empty());
+        let shaded: f32 = solid.shading.iter().map(|p| p.unsigned_area()).sum();
+        assert!(shaded > 100. * 4. * 20.);
+        for cell in &solid.shading {
+            let cell = cell.map_coords(|c| coord! {x: c.x as f64, y: c.y as f64});
+            assert!(area.intersection(&MultiPolygon::from(cell)).unsigned_area() < 1e-6);
+        }
+
+        assert!(hatch(HatchPattern::None).is_empty());
+    }
+
+    #[test]
+    fn hatch_style_parameters() {
+        let area = square(100.);
+        let default = HatchStyle::default();
+        let band = |style: &HatchStyle| {
+            hatch_area(&area, style)
+                .lines
+                .iter()
+                .flat_map(|l| [l.start(), l.end()])
+                .map(|c| area.euclidean_distance(&Point::new(c.x as f64, c.y as f64)))
+                .fold(0., f64::max)
+        };
+        //a wider offset pushes the hatching further out
+        let wide = HatchStyle {
+            offset: 120.,
+            ..default
+        };
+        assert!(band(&wide) > band(&default) + 30.);
+
+        let sparse = HatchStyle {
+            hatch_count: 3,
+            ..default
+        };
+        assert!(hatch_area(&area, &sparse).lines.len() < hatch_area(&area, &default).lines.len());
+
+        let long_only = HatchStyle {
+            min_length: 25.,
+            ..default
+        };
+        assert!(hatch_area(&area, &long_only)
+            .lines
+            .iter()
+            .all(|l| geo::Line::new(l.start(), l.end()).euclidean_length() > 25.));
+
+        let reseeded = HatchStyle { seed: 1, ..default };
+        assert_ne!(
+            hatch_area(&area, &reseeded).lines,
+            hatch_area(&area, &default).lines
+        );
+    }
+
+    //Two rooms with a narrow gap between them: the cells in the gap are cut down to
+    //thin slivers, which used to lose every stroke to the short-stroke filter
+    #[test]
+    fn thin_gaps_stay_hatched() {
+        let mut area = room(0., 0., 200.);
+        area = area.union(&room(206., 0., 200.));
+        //share of points down the middle of the gap that have a stroke nearby.
+        //Any one seed can leave a few holes, so it's taken over several
+        let samples: Vec<_> = (0..40)
+            .map(|i| Point::new(203., 5. + i as f64 * 5.))
+            .collect();
+        let mut covered = 0;
+        for seed in 0..10 {
+            let style = HatchStyle {
+                seed,
+                ..HatchStyle::default()
+            };
+            let lines: Vec<_> = hatch_area(&area, &style)
+                .lines
+                .iter()
+                .map(|l| geo::Line::new(l.start(), l.end()))
+                .map(|l| l.map_coords(|c| coord! {x: c.x as f64, y: c.y as f64}))
+                .collect();
+            covered += samples
+                .iter()
+                .filter(|p| lines.iter().any(|l| l.euclidean_distance(*p) < 4.))
+                .count();
+        }
+        assert!(covered as f64 / (samples.len() * 10) as f64 > 0.9);
+    }
+}
@@ -0,0 +1,10 @@
+//! The map model behind Macromapper: layers, their geometry and hatching, undoable edits,
+//! and the saved file format. Nothing in here touches the GPU or the UI, so maps can be
+//! built, edited and exported from other tools and tests.
+
+pub mod file;
+pub mod grid;
+pub mod hatching;
+pub mod map;
+pub mod sketch;
+pub mod svg;
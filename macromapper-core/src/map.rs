@@ -0,0 +1,1324 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::grid::{GridMode, GridSettings};
+use crate::hatching::{hatch_tiles, tiles_near, HatchStyle, TileCoord, TileHatching};
+use crate::sketch::{clip_sketch, Canvas, Color, Fill, Line, Sketch, BLACK, GRAY, WHITE};
+use geo::*;
+use serde::{Deserialize, Serialize};
+use undo::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LayerStyle {
+    pub outline_thickness: f32,
+    pub outline_color: Color,
+    pub hatch_thickness: f32,
+    pub hatch_color: Color,
+    pub floor_color: Color,
+}
+
+impl Default for LayerStyle {
+    fn default() -> Self {
+        LayerStyle {
+            outline_thickness: 3.0,
+            outline_color: BLACK,
+            hatch_thickness: 2.0,
+            hatch_color: GRAY,
+            floor_color: WHITE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "LayerData", into = "LayerData")]
+pub struct Layer {
+    area: MultiPolygon,
+    area_sketch: Sketch,
+    floor: Fill,
+    hatching: Sketch,
+    shading: Fill,
+    name: String,
+    style: LayerStyle,
+    hatch: HatchStyle,
+    visible: bool,
+    locked: bool,
+    //Hatching is generated in the background, so results are matched back to the layer by id,
+    //and to the shape of its area by revision. Revisions are unique across all layers.
+    id: u64,
+    revision: u64,
+    hatch_tiles: BTreeMap<TileCoord, TileHatching>,
+    dirty_tiles: BTreeSet<TileCoord>,
+}
+
+//ids and revisions only have to be unique while the program runs, so they aren't saved
+fn next_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+//The ids and revisions are bookkeeping, two layers are the same if they look the same
+impl PartialEq for Layer {
+    fn eq(&self, other: &Self) -> bool {
+        self.area == other.area
+            && self.area_sketch == other.area_sketch
+            && self.floor == other.floor
+            && self.hatching == other.hatching
+            && self.shading == other.shading
+            && self.name == other.name
+            && self.style == other.style
+            && self.hatch == other.hatch
+            && self.visible == other.visible
+            && self.locked == other.locked
+    }
+}
+
+//Layers stored in the edit history (e.g. a deleted layer) leave out the derived sketches
+#[derive(Serialize, Deserialize)]
+struct LayerData {
+    name: String,
+    style: LayerStyle,
+    area: MultiPolygon,
+    hatch: HatchStyle,
+    visible: bool,
+    locked: bool,
+}
+
+impl From<LayerData> for Layer {
+    fn from(data: LayerData) -> Self {
+        let mut layer = Layer::with_area(data.name, data.style, data.area);
+        //with_area picked its tiles for the default band, which may be narrower
+        layer.set_hatch(data.hatch);
+        layer.visible = data.visible;
+        layer.locked = data.locked;
+        layer
+    }
+}
+
+impl From<Layer> for LayerData {
+    fn from(layer: Layer) -> Self {
+        LayerData {
+            name: layer.name,
+            style: layer.style,
+            area: layer.area,
+            hatch: layer.hatch,
+            visible: layer.visible,
+            locked: layer.locked,
+        }
+    }
+}
+
+impl Layer {
+    pub fn new(name: String) -> Self {
+        Self::with_area(name, LayerStyle::default(), MultiPolygon(vec![]))
+    }
+    //sketch and hatching are derived from the area, so they get rebuilt instead of stored.
+    //The hatching starts out stale, see Hatcher and Map::regenerate_hatching
+    pub fn with_area(name: String, style: LayerStyle, area: MultiPolygon) -> Self {
+        let mut layer = Layer {
+            area,
+            area_sketch: Sketch::new(style.outline_thickness, style.outline_color),
+            floor: Fill::new(style.floor_color),
+            hatching: Sketch::new(style.hatch_thickness, style.hatch_color),
+            shading: Fill::new(style.hatch_color),
+            name,
+            style,
+            hatch: HatchStyle::default(),
+            visible: true,
+            locked: false,
+            id: next_id(),
+            revision: 0,
+            hatch_tiles: BTreeMap::new(),
+            dirty_tiles: BTreeSet::new(),
+        };
+        let changed = layer.area.bounding_rect();
+        layer.area_changed(changed);
+        layer
+    }
+    pub fn area(&self) -> &MultiPolygon {
+        &self.area
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn style(&self) -> &LayerStyle {
+        &self.style
+    }
+    pub fn hatch(&self) -> &HatchStyle {
+        &self.hatch
+    }
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+    //Only used when loading, edits go through MapToggleLayer so they can be undone
+    pub fn set_flags(&mut self, visible: bool, locked: bool) {
+        self.visible = visible;
+        self.locked = locked;
+    }
+    //Same as set_flags, MapHatchLayer is the undoable version
+    pub fn set_hatch(&mut self, hatch: HatchStyle) {
+        self.hatch = hatch;
+        self.rehatch();
+    }
+    pub fn area_sketch(&self) -> &Sketch {
+        &self.area_sketch
+    }
+    pub fn floor(&self) -> &Fill {
+        &self.floor
+    }
+    pub fn hatching(&self) -> &Sketch {
+        &self.hatching
+    }
+    pub fn shading(&self) -> &Fill {
+        &self.shading
+    }
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    //true until every tile touched by an edit has new hatching
+    pub fn hatching_stale(&self) -> bool {
+        !self.dirty_tiles.is_empty()
+    }
+    pub fn hatch_tiles(&self) -> impl Iterator<Item = (TileCoord, &TileHatching)> {
+        self.hatch_tiles
+            .iter()
+            .map(|(tile, hatching)| (*tile, hatching))
+    }
+    pub fn dirty_tiles(&self) -> impl Iterator<Item = TileCoord> + '_ {
+        self.dirty_tiles.iter().copied()
+    }
+    //Styles only change how the sketches are stroked, so nothing has to be regenerated
+    fn apply_style(&mut self) {
+        self.area_sketch
+            .set_stroke(self.style.outline_thickness, self.style.outline_color);
+        self.hatching
+            .set_stroke(self.style.hatch_thickness, self.style.hatch_color);
+        self.shading.set_color(self.style.hatch_color);
+        self.floor.set_color(self.style.floor_color);
+    }
+    //For changes that need all of the hatching redone, like a new pattern.
+    //The old tiles are included in case the band got narrower and they end up empty
+    fn rehatch(&mut self) {
+        self.revision = next_id();
+        self.dirty_tiles.extend(self.hatch_tiles.keys());
+        if let Some(rect) = self.area.bounding_rect() {
+            self.dirty_tiles.extend(tiles_near(rect, &self.hatch));
+        }
+    }
+    //The outline and floor are cheap enough to rebuild right away, the hatching is left for later.
+    //changed has to cover everywhere the area might be different, old or new
+    fn area_changed(&mut self, changed: Option<geo::Rect>) {
+        self.area_sketch.clear();
+        for l in self.area.lines_iter() {
+            self.area_sketch.add(Line::from_geo(l));
+        }
+        self.floor.clear();
+        for polygon in &self.area {
+            self.floor
+                .add(polygon.map_coords(|c| coord! {x: c.x as f32, y: c.y as f32}));
+        }
+        self.revision = next_id();
+        if let Some(rect) = changed {
+            self.dirty_tiles.extend(tiles_near(rect, &self.hatch));
+        }
+    }
+    fn set_hatching(&mut self, tiles: Vec<(TileCoord, TileHatching)>) {
+        for (tile, hatching) in tiles {
+            self.dirty_tiles.remove(&tile);
+            if hatching.is_empty() {
+                self.hatch_tiles.remove(&tile);
+            } else {
+                self.hatch_tiles.insert(tile, hatching);
+            }
+        }
+        self.hatching.clear();
+        self.shading.clear();
+        for tile in self.hatch_tiles.values() {
+            for line in &tile.lines {
+                self.hatching.add(line.clone());
+            }
+            for polygon in &tile.shading {
+                self.shading.add(polygon.clone());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Map {
+    layers: Vec<Layer>,
+    grid: GridSettings,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+    pub fn from_layers(layers: Vec<Layer>, grid: GridSettings) -> Self {
+        Map { layers, grid }
+    }
+    pub fn grid_settings(&self) -> &GridSettings {
+        &self.grid
+    }
+    pub fn append_layer(&mut self) {
+        self.layers.push(Layer::new(self.new_layer_name()));
+    }
+    //The lowest number that isn't taken yet, so deleting a layer never leads to two with one name
+    pub fn new_layer_name(&self) -> String {
+        (0..)
+            .map(|n| format!("layer-{n}"))
+            .find(|name| self.layers.iter().all(|l| &l.name != name))
+            .expect("some number is always free")
+    }
+    pub fn layer(&self, index: usize) -> Option<&Layer> {
+        self.layers.get(index)
+    }
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+    pub fn layers_iter(&self) -> core::slice::Iter<'_, Layer> {
+        self.layers.iter()
+    }
+    //Hidden layers are left out of the canvas and every export
+    pub fn visible_layers(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter().filter(|l| l.visible)
+    }
+    //The view and every export draw through here, so they all stack things the same way.
+    //Floors go under the grid so that it still shows inside rooms, then each layer's
+    //shading, hatching and outline go on top.
+    pub fn draw(&self, grid: Option<&Sketch>, canvas: &mut impl Canvas) {
+        for l in self.visible_layers() {
+            canvas.fill(l.floor());
+        }
+        if let Some(grid) = grid {
+            canvas.sketch(grid);
+        }
+        for l in self.visible_layers() {
+            canvas.fill(l.shading());
+            canvas.sketch(l.hatching());
+            canvas.sketch(l.area_sketch());
+        }
+    }
+    //Drops the result if the layer is gone or its area changed after the hatching was requested
+    pub fn set_hatching(
+        &mut self,
+        layer: u64,
+        revision: u64,
+        tiles: Vec<(TileCoord, TileHatching)>,
+    ) {
+        if let Some(target_layer) = self
+            .layers
+            .iter_mut()
+            .find(|l| l.id == layer && l.revision == revision)
+        {
+            target_layer.set_hatching(tiles);
+        }
+    }
+    //Hatches every stale layer right away, for when there's no Hatcher to wait for
+    pub fn regenerate_hatching(&mut self) {
+        for layer in self.layers.iter_mut().filter(|l| l.hatching_stale()) {
+            let tiles: Vec<_> = layer.dirty_tiles().collect();
+            let hatching = hatch_tiles(&layer.area, &layer.hatch, &tiles);
+            layer.set_hatching(hatching);
+        }
+    }
+    //Everything on the visible layers as a single area
+    pub fn visible_area(&self) -> MultiPolygon {
+        self.visible_layers()
+            .fold(MultiPolygon::new(vec![]), |area, l| area.union(&l.area))
+    }
+    //Grid lines over rect, or None if the grid is hidden
+    pub fn grid(&self, rect: geo::Rect<f32>, mode: GridMode) -> Option<Sketch> {
+        let grid = self.grid.grid();
+        match mode {
+            GridMode::Full => Some(grid.sketch(rect)),
+            GridMode::InsideOnly => Some(clip_sketch(&grid.sketch(rect), &self.visible_area())),
+            GridMode::Hidden => None,
+        }
+    }
+    pub fn bounding_rect(&self) -> Option<geo::Rect> {
+        self.visible_layers()
+            .filter_map(|l| l.area.bounding_rect())
+            .reduce(|a, b| {
+                geo::Rect::new(
+                    coord! {x: a.min().x.min(b.min().x), y: a.min().y.min(b.min().y)},
+                    coord! {x: a.max().x.max(b.max().x), y: a.max().y.max(b.max().y)},
+                )
+            })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum MapEdit {
+    Union(MapUnion),
+    Subtraction(MapSubtraction),
+    AddLayer(MapAddLayer),
+    RemoveLayer(MapRemoveLayer),
+    RenameLayer(MapRenameLayer),
+    MoveLayer(MapMoveLayer),
+    ToggleLayer(MapToggleLayer),
+    StyleLayer(MapStyleLayer),
+    HatchLayer(MapHatchLayer),
+    ChangeGrid(MapChangeGrid),
+    Move(MapMove),
+    Reshape(MapReshape),
+}
+
+//boring boilerplate to make things work
+//check edit and undo methods for each operation for implementation details
+impl Edit for MapEdit {
+    type Target = Map;
+    type Output = ();
+
+    fn edit(&mut self, target: &mut Self::Target) -> Self::Output {
+        match self {
+            MapEdit::Union(u) => u.edit(target),
+            MapEdit::Subtraction(s) => s.edit(target),
+            MapEdit::AddLayer(a) => a.edit(target),
+            MapEdit::RemoveLayer(r) => r.edit(target),
+            MapEdit::RenameLayer(r) => r.apply(target),
+            MapEdit::MoveLayer(m) => m.edit(target),
+            MapEdit::ToggleLayer(t) => t.apply(target),
+            MapEdit::StyleLayer(s) => s.apply(target),
+            MapEdit::HatchLayer(h) => h.apply(target),
+            MapEdit::ChangeGrid(g) => g.apply(target),
+            MapEdit::Move(m) => m.edit(target),
+            MapEdit::Reshape(r) => r.edit(target),
+        }
+    }
+    fn undo(&mut self, target: &mut Self::Target) -> Self::Output {
+        match self {
+            MapEdit::Union(u) => u.undo(target),
+            MapEdit::Subtraction(s) => s.undo(target),
+            MapEdit::AddLayer(a) => a.undo(target),
+            MapEdit::RemoveLayer(r) => r.undo(target),
+            MapEdit::RenameLayer(r) => r.apply(target),
+            MapEdit::MoveLayer(m) => m.undo(target),
+            MapEdit::ToggleLayer(t) => t.apply(target),
+            MapEdit::StyleLayer(s) => s.apply(target),
+            MapEdit::HatchLayer(h) => h.apply(target),
+            MapEdit::ChangeGrid(g) => g.apply(target),
+            MapEdit::Move(m) => m.undo(target),
+            MapEdit::Reshape(r) => r.undo(target),
+        }
+    }
+    fn merge(&mut self, other: Self) -> Merged<Self> {
+        match (self, other) {
+            (MapEdit::StyleLayer(s), MapEdit::StyleLayer(o))
+                if s.index == o.index && same_gesture(s.gesture, o.gesture) =>
+            {
+                Merged::Yes
+            }
+            (MapEdit::HatchLayer(h), MapEdit::HatchLayer(o))
+                if h.index == o.index && same_gesture(h.gesture, o.gesture) =>
+            {
+                Merged::Yes
+            }
+            (MapEdit::ChangeGrid(g), MapEdit::ChangeGrid(o))
+                if same_gesture(g.gesture, o.gesture) =>
+            {
+                Merged::Yes
+            }
+            (_, other) => Merged::No(other),
+        }
+    }
+}
+
+//None means the edit didn't come from a drag or typing, so it's never merged
+fn same_gesture(a: Option<u64>, b: Option<u64>) -> bool {
+    a.is_some() && a == b
+}
+
+//Saved edits come from files that might be corrupt, and a bad index would only panic once the
+//edit is undone or redone. These let the loader check everything up front.
+impl MapEdit {
+    //How many layers applying the edit adds (or removes, if negative)
+    pub fn layer_delta(&self) -> isize {
+        match self {
+            MapEdit::AddLayer(_) => 1,
+            MapEdit::RemoveLayer(_) => -1,
+            _ => 0,
+        }
+    }
+    //Whether the edit can be applied to (or undone from) a map with layer_count layers before
+    //it. applied is whether the map currently has the edit applied
+    pub fn check(&self, layer_count: usize, applied: bool) -> Result<(), &'static str> {
+        let fits = match self {
+            MapEdit::Union(u) => u.layer < layer_count,
+            MapEdit::Subtraction(s) => s.layer < layer_count,
+            MapEdit::AddLayer(a) => a.index <= layer_count,
+            MapEdit::RemoveLayer(r) => r.index < layer_count,
+            MapEdit::RenameLayer(r) => r.index < layer_count,
+            MapEdit::MoveLayer(m) => m.from < layer_count && m.to < layer_count,
+            MapEdit::ToggleLayer(t) => t.index < layer_count,
+            MapEdit::StyleLayer(s) => s.index < layer_count,
+            MapEdit::HatchLayer(h) => h.index < layer_count,
+            MapEdit::ChangeGrid(_) => true,
+            MapEdit::Move(m) => m.layer < layer_count,
+            MapEdit::Reshape(r) => r.layer < layer_count,
+        };
+        if !fits {
+            return Err("edit refers to a layer that doesn't exist");
+        }
+        match self {
+            //the removed layer is kept only while the removal is applied
+            MapEdit::RemoveLayer(r) if applied && r.layer.is_none() => {
+                Err("removed layer is missing")
+            }
+            MapEdit::Move(m) if !(m.offset.x.is_finite() && m.offset.y.is_finite()) => {
+                Err("move has a non-finite offset")
+            }
+            _ => Ok(()),
+        }
+    }
+    //Every area stored in the edit
+    pub fn areas(&self) -> Vec<&MultiPolygon> {
+        match self {
+            MapEdit::Union(u) => vec![&u.base, &u.operator],
+            MapEdit::Subtraction(s) => vec![&s.base, &s.operator],
+            MapEdit::AddLayer(a) => vec![&a.layer.area],
+            MapEdit::RemoveLayer(r) => r.layer.iter().map(|l| &l.area).collect(),
+            MapEdit::Move(m) => vec![&m.base, &m.polygons],
+            MapEdit::Reshape(r) => vec![&r.base, &r.old, &r.new],
+            _ => vec![],
+        }
+    }
+    pub fn grid(&self) -> Option<&GridSettings> {
+        match self {
+            MapEdit::ChangeGrid(g) => Some(&g.grid),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapUnion {
+    base: MultiPolygon,
+    operator: MultiPolygon,
+    layer: usize,
+}
+
+impl MapUnion {
+    pub fn new(layer: usize, operator: MultiPolygon) -> Self {
+        Self {
+            base: MultiPolygon(vec![]),
+            operator,
+            layer,
+        }
+    }
+    fn edit(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        self.base = target_layer.area.intersection(&self.operator);
+        target_layer.area = target_layer.area.union(&self.operator);
+        target_layer.area_changed(self.operator.bounding_rect());
+    }
+    fn undo(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        target_layer.area = target_layer
+            .area
+            .difference(&self.operator)
+            .union(&self.base);
+        target_layer.area_changed(self.operator.bounding_rect());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapSubtraction {
+    base: MultiPolygon,
+    operator: MultiPolygon,
+    layer: usize,
+}
+
+impl MapSubtraction {
+    pub fn new(layer: usize, operator: MultiPolygon) -> Self {
+        Self {
+            base: MultiPolygon(vec![]),
+            operator,
+            layer,
+        }
+    }
+    fn edit(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        self.base = target_layer.area.intersection(&self.operator);
+        target_layer.area = target_layer.area.difference(&self.operator);
+        target_layer.area_changed(self.operator.bounding_rect());
+    }
+    fn undo(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        target_layer.area = target_layer.area.union(&self.base);
+        target_layer.area_changed(self.operator.bounding_rect());
+    }
+}
+
+//Moving and reshaping both take some polygons out of a layer and put others in.
+//Like a union, base keeps whatever the new ones covered so undoing doesn't cut it away
+fn replace_area(layer: &mut Layer, old: &MultiPolygon, new: &MultiPolygon) -> MultiPolygon {
+    let rest = layer.area.difference(old);
+    let base = rest.intersection(new);
+    layer.area = rest.union(new);
+    layer.area_changed(combined_rect(old, new));
+    base
+}
+
+fn restore_area(layer: &mut Layer, old: &MultiPolygon, new: &MultiPolygon, base: &MultiPolygon) {
+    layer.area = layer.area.difference(new).union(base).union(old);
+    layer.area_changed(combined_rect(old, new));
+}
+
+fn combined_rect(a: &MultiPolygon, b: &MultiPolygon) -> Option<geo::Rect> {
+    MultiPolygon(a.iter().chain(b).cloned().collect()).bounding_rect()
+}
+
+//Moves some of a layer's polygons by offset
+#[derive(Serialize, Deserialize)]
+pub struct MapMove {
+    base: MultiPolygon,
+    polygons: MultiPolygon,
+    offset: Coord,
+    layer: usize,
+}
+
+impl MapMove {
+    pub fn new(layer: usize, polygons: MultiPolygon, offset: Coord) -> Self {
+        Self {
+            base: MultiPolygon(vec![]),
+            polygons,
+            offset,
+            layer,
+        }
+    }
+    fn moved(&self) -> MultiPolygon {
+        self.polygons.translate(self.offset.x, self.offset.y)
+    }
+    fn edit(&mut self, target: &mut Map) {
+        let moved = self.moved();
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        self.base = replace_area(target_layer, &self.polygons, &moved);
+    }
+    fn undo(&mut self, target: &mut Map) {
+        let moved = self.moved();
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        restore_area(target_layer, &self.polygons, &moved, &self.base);
+    }
+}
+
+//Swaps one of a layer's polygons for a changed copy, e.g. with a vertex moved or removed
+#[derive(Serialize, Deserialize)]
+pub struct MapReshape {
+    base: MultiPolygon,
+    old: MultiPolygon,
+    new: MultiPolygon,
+    layer: usize,
+}
+
+impl MapReshape {
+    pub fn new(layer: usize, old: Polygon, new: Polygon) -> Self {
+        Self {
+            base: MultiPolygon(vec![]),
+            old: MultiPolygon(vec![old]),
+            new: MultiPolygon(vec![new]),
+            layer,
+        }
+    }
+    fn edit(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        self.base = replace_area(target_layer, &self.old, &self.new);
+    }
+    fn undo(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.layer)
+            .expect("layer should exist");
+        restore_area(target_layer, &self.old, &self.new, &self.base);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapAddLayer {
+    index: usize,
+    layer: Layer,
+}
+
+impl MapAddLayer {
+    pub fn new(index: usize, layer: Layer) -> Self {
+        Self { index, layer }
+    }
+    fn edit(&mut self, target: &mut Map) {
+        target.layers.insert(self.index, self.layer.clone());
+    }
+    fn undo(&mut self, target: &mut Map) {
+        target.layers.remove(self.index);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapRemoveLayer {
+    index: usize,
+    layer: Option<Layer>,
+}
+
+impl MapRemoveLayer {
+    pub fn new(index: usize) -> Self {
+        Self { index, layer: None }
+    }
+    fn edit(&mut self, target: &mut Map) {
+        self.layer = Some(target.layers.remove(self.index));
+    }
+    fn undo(&mut self, target: &mut Map) {
+        let layer = self.layer.take().expect("layer should have been removed");
+        target.layers.insert(self.index, layer);
+    }
+}
+
+//Renaming, toggling and styling swap the stored value with the layer's,
+//so edit and undo are the same operation
+#[derive(Serialize, Deserialize)]
+pub struct MapRenameLayer {
+    index: usize,
+    name: String,
+}
+
+impl MapRenameLayer {
+    pub fn new(index: usize, name: String) -> Self {
+        Self { index, name }
+    }
+    fn apply(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.index)
+            .expect("layer should exist");
+        std::mem::swap(&mut target_layer.name, &mut self.name);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapMoveLayer {
+    from: usize,
+    to: usize,
+}
+
+impl MapMoveLayer {
+    pub fn new(from: usize, to: usize) -> Self {
+        Self { from, to }
+    }
+    fn edit(&mut self, target: &mut Map) {
+        let layer = target.layers.remove(self.from);
+        target.layers.insert(self.to, layer);
+    }
+    fn undo(&mut self, target: &mut Map) {
+        let layer = target.layers.remove(self.to);
+        target.layers.insert(self.from, layer);
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum LayerFlag {
+    Visible,
+    Locked,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapToggleLayer {
+    index: usize,
+    flag: LayerFlag,
+}
+
+impl MapToggleLayer {
+    pub fn new(index: usize, flag: LayerFlag) -> Self {
+        Self { index, flag }
+    }
+    fn apply(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.index)
+            .expect("layer should exist");
+        match self.flag {
+            LayerFlag::Visible => target_layer.visible = !target_layer.visible,
+            LayerFlag::Locked => target_layer.locked = !target_layer.locked,
+        }
+    }
+}
+
+//Dragging a slider or colour picker sends a new style every frame. Style edits on the same
+//layer are merged while they're part of one gesture, keeping only the style from before it.
+//The gesture is whatever id the ui gives a single drag, it isn't saved.
+#[derive(Serialize, Deserialize)]
+pub struct MapStyleLayer {
+    index: usize,
+    style: LayerStyle,
+    #[serde(skip)]
+    gesture: Option<u64>,
+}
+
+impl MapStyleLayer {
+    pub fn new(index: usize, style: LayerStyle) -> Self {
+        Self {
+            index,
+            style,
+            gesture: None,
+        }
+    }
+    pub fn with_gesture(mut self, gesture: Option<u64>) -> Self {
+        self.gesture = gesture;
+        self
+    }
+    fn apply(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.index)
+            .expect("layer should exist");
+        std::mem::swap(&mut target_layer.style, &mut self.style);
+        target_layer.apply_style();
+    }
+}
+
+//Merged the same way as style edits, so the hatching sliders can preview live
+#[derive(Serialize, Deserialize)]
+pub struct MapHatchLayer {
+    index: usize,
+    hatch: HatchStyle,
+    #[serde(skip)]
+    gesture: Option<u64>,
+}
+
+impl MapHatchLayer {
+    pub fn new(index: usize, hatch: HatchStyle) -> Self {
+        Self {
+            index,
+            hatch,
+            gesture: None,
+        }
+    }
+    pub fn with_gesture(mut self, gesture: Option<u64>) -> Self {
+        self.gesture = gesture;
+        self
+    }
+    fn apply(&mut self, target: &mut Map) {
+        let target_layer = target
+            .layers
+            .get_mut(self.index)
+            .expect("layer should exist");
+        std::mem::swap(&mut target_layer.hatch, &mut self.hatch);
+        target_layer.rehatch();
+    }
+}
+
+//Swaps the map's grid settings for new ones, undoing swaps them back.
+//Merged like style edits, so dragging the cell size or typing a unit is one step
+#[derive(Serialize, Deserialize)]
+pub struct MapChangeGrid {
+    grid: GridSettings,
+    #[serde(skip)]
+    gesture: Option<u64>,
+}
+
+impl MapChangeGrid {
+    pub fn new(grid: GridSettings) -> Self {
+        Self {
+            grid,
+            gesture: None,
+        }
+    }
+    pub fn with_gesture(mut self, gesture: Option<u64>) -> Self {
+        self.gesture = gesture;
+        self
+    }
+    fn apply(&mut self, target: &mut Map) {
+        std::mem::swap(&mut target.grid, &mut self.grid);
+    }
+}
+
+#[derive(Clone)]
+pub enum PolyOpType {
+    Union,
+    Subtraction,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::GridKind;
+    use crate::hatching::hatch_area;
+
+    #[test]
+    fn basic_undo_redo() {
+        let mut test_map: Map = Map {
+            layers: vec![Layer::new("test".to_string())],
+            ..Map::default()
+        };
+        let mut history: History<_> = History::new();
+        history.edit(
+            &mut test_map,
+            MapEdit::Union(MapUnion::new(
+                0,
+                MultiPolygon::new(vec![polygon![
+                    (x: 0., y: 0.),
+                    (x: 0., y: 1.),
+                    (x: 1., y: 1.),
+                    (x: 1., y: 0.)
+                ]]),
+            )),
+        );
+        let one = test_map.clone();
+        history.edit(
+            &mut test_map,
+            MapEdit::Union(MapUnion::new(
+                0,
+                MultiPolygon::new(vec![polygon![
+                    (x: 1., y: 0.),
+                    (x: 1., y: 0.5),
+                    (x: 2., y: 0.5),
+                    (x: 2., y: 0.)
+                ]]),
+            )),
+        );
+        let two = test_map.clone();
+        history.undo(&mut test_map);
+        let three = test_map.clone();
+        history.redo(&mut test_map);
+        let four = test_map.clone();
+        history.edit(
+            &mut test_map,
+            MapEdit::Union(MapUnion::new(
+                0,
+                MultiPolygon::new(vec![polygon![
+                    (x: 0., y: 0.),
+                    (x: 0., y: 1.),
+                    (x: 1., y: 1.),
+                    (x: 1., y: 0.)
+                ]]),
+            )),
+        );
+        let five = test_map.clone();
+        history.undo(&mut test_map);
+        let six = test_map.clone();
+        history.redo(&mut test_map);
+        let seven = test_map.clone();
+
+        //Points are different so comparing whole layers would fail even if behavior is correct
+        //I compare areas to ensure that the geometry makes sense without requiring the internals to be the same
+        assert_eq!(
+            one.layers.first().unwrap().area.unsigned_area(),
+            three.layers.first().unwrap().area.unsigned_area()
+        );
+        assert_eq!(
+            two.layers.first().unwrap().area.unsigned_area(),
+            four.layers.first().unwrap().area.unsigned_area()
+        );
+        assert_eq!(
+            four.layers.first().unwrap().area.unsigned_area(),
+            six.layers.first().unwrap().area.unsigned_area()
+        );
+        assert_eq!(
+            five.layers.first().unwrap().area.unsigned_area(),
+            seven.layers.first().unwrap().area.unsigned_area()
+        );
+    }
+
+    fn test_map() -> Map {
+        let mut map = Map::new();
+        map.append_layer();
+        map.append_layer();
+        map.layers[1] = Layer::with_area(
+            "walls".to_string(),
+            LayerStyle::default(),
+            MultiPolygon::new(vec![polygon![
+                (x: 0., y: 0.),
+                (x: 0., y: 100.),
+                (x: 100., y: 100.),
+                (x: 100., y: 0.)
+            ]]),
+        );
+        map
+    }
+
+    fn names(map: &Map) -> Vec<&str> {
+        map.layers_iter().map(|l| l.name()).collect()
+    }
+
+    //Applies the edit, then checks that undo gets back the original layers and redo the edited ones
+    fn check_undo_redo(edit: MapEdit) -> Map {
+        let mut map = test_map();
+        let original = map.layers.clone();
+        let mut history: History<_> = History::new();
+        history.edit(&mut map, edit);
+        let edited = map.layers.clone();
+        history.undo(&mut map);
+        assert_eq!(map.layers, original);
+        history.redo(&mut map);
+        assert_eq!(map.layers, edited);
+        map
+    }
+
+    #[test]
+    fn add_layer_undo_redo() {
+        let map = check_undo_redo(MapEdit::AddLayer(MapAddLayer::new(
+            1,
+            Layer::new("new".to_string()),
+        )));
+        assert_eq!(names(&map), ["layer-0", "new", "walls"]);
+    }
+
+    #[test]
+    fn remove_layer_undo_redo() {
+        let map = check_undo_redo(MapEdit::RemoveLayer(MapRemoveLayer::new(1)));
+        assert_eq!(names(&map), ["layer-0"]);
+    }
+
+    #[test]
+    fn new_layer_names_stay_unique() {
+        let mut map = Map::new();
+        map.append_layer();
+        map.append_layer();
+        map.append_layer();
+        map.layers.remove(1);
+        map.append_layer();
+        assert_eq!(names(&map), ["layer-0", "layer-2", "layer-1"]);
+        map.append_layer();
+        assert_eq!(names(&map), ["layer-0", "layer-2", "layer-1", "layer-3"]);
+    }
+
+    #[test]
+    fn rename_layer_undo_redo() {
+        let map = check_undo_redo(MapEdit::RenameLayer(MapRenameLayer::new(
+            1,
+            "rooms".to_string(),
+        )));
+        assert_eq!(names(&map), ["layer-0", "rooms"]);
+    }
+
+    #[test]
+    fn move_layer_undo_redo() {
+        let map = check_undo_redo(MapEdit::MoveLayer(MapMoveLayer::new(1, 0)));
+        assert_eq!(names(&map), ["walls", "layer-0"]);
+    }
+
+    #[test]
+    fn toggle_layer_undo_redo() {
+        let map = check_undo_redo(MapEdit::ToggleLayer(MapToggleLayer::new(
+            1,
+            LayerFlag::Visible,
+        )));
+        assert!(!map.layers[1].visible());
+        assert_eq!(map.visible_layers().count(), 1);
+        let map = check_undo_redo(MapEdit::ToggleLayer(MapToggleLayer::new(
+            0,
+            LayerFlag::Locked,
+        )));
+        assert!(map.layers[0].locked());
+    }
+
+    #[test]
+    fn style_layer_undo_redo() {
+        let style = LayerStyle {
+            outline_thickness: 5.0,
+            ..LayerStyle::default()
+        };
+        let map = check_undo_redo(MapEdit::StyleLayer(MapStyleLayer::new(1, style)));
+        assert_eq!(map.layers[1].area_sketch().thickness(), 5.0);
+        //restyling keeps the geometry
+        assert_eq!(
+            map.layers[1].area_sketch().lines(),
+            test_map().layers[1].area_sketch().lines()
+        );
+    }
+
+    #[test]
+    fn rebuilt_layers_hatch_their_whole_band() {
+        let mut layer = test_map().layers.remove(1);
+        layer.set_hatch(HatchStyle {
+            offset: 150.,
+            ..Default::default()
+        });
+        let expected = hatch_area(&layer.area, &layer.hatch).lines;
+        let mut map = Map::from_layers(
+            vec![Layer::from(LayerData::from(layer))],
+            GridSettings::default(),
+        );
+        map.regenerate_hatching();
+        assert_eq!(map.layers[0].hatching().lines(), expected);
+    }
+
+    #[test]
+    fn floor_follows_area() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history: History<_> = History::new();
+        let square = |min: f64, max: f64| {
+            MultiPolygon::new(vec![polygon![
+                (x: min, y: min),
+                (x: min, y: max),
+                (x: max, y: max),
+                (x: max, y: min)
+            ]])
+        };
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, square(0., 100.))));
+        history.edit(
+            &mut map,
+            MapEdit::Subtraction(MapSubtraction::new(0, square(40., 60.))),
+        );
+        //the hole is left unfilled
+        let floor = map.layers[0].floor().polygons();
+        assert_eq!(floor.len(), 1);
+        assert_eq!(floor[0].interiors().len(), 1);
+
+        let style = LayerStyle {
+            floor_color: BLACK,
+            ..LayerStyle::default()
+        };
+        history.edit(&mut map, MapEdit::StyleLayer(MapStyleLayer::new(0, style)));
+        assert_eq!(map.layers[0].floor().color(), BLACK);
+        history.undo(&mut map);
+        assert_eq!(map.layers[0].floor().color(), WHITE);
+    }
+
+    #[test]
+    fn change_grid_undo_redo() {
+        let mut map = test_map();
+        let mut history: History<_> = History::new();
+        let hex = GridSettings {
+            kind: GridKind::HexFlat,
+            ..GridSettings::default()
+        };
+        history.edit(
+            &mut map,
+            MapEdit::ChangeGrid(MapChangeGrid::new(hex.clone())),
+        );
+        assert_eq!(map.grid_settings(), &hex);
+        //one drag of the size slider is one step, separate from picking the shape
+        for size in [60., 70.] {
+            let resized = GridSettings {
+                size,
+                ..hex.clone()
+            };
+            let edit = MapChangeGrid::new(resized).with_gesture(Some(1));
+            history.edit(&mut map, MapEdit::ChangeGrid(edit));
+        }
+        assert_eq!(map.grid_settings().size, 70.);
+        history.undo(&mut map);
+        assert_eq!(map.grid_settings(), &hex);
+        history.undo(&mut map);
+        assert_eq!(map.grid_settings(), &GridSettings::default());
+        assert!(!history.can_undo());
+        history.redo(&mut map);
+        history.redo(&mut map);
+        assert_eq!(map.grid_settings().size, 70.);
+    }
+
+    #[test]
+    fn grid_modes() {
+        let mut map = test_map();
+        let view = geo::Rect::new(coord! {x: -500., y: -500.}, coord! {x: 500., y: 500.});
+        let full = map.grid(view, GridMode::Full).unwrap();
+        let inside = map.grid(view, GridMode::InsideOnly).unwrap();
+        assert!(!inside.lines().is_empty());
+        assert!(inside.lines().len() < full.lines().len());
+        //every clipped line stays within the drawn area
+        let area = map.visible_area();
+        for line in inside.lines() {
+            let middle = (line.start() + line.end()) / 2.;
+            let middle = Point::new(middle.x as f64, middle.y as f64);
+            assert!(area.euclidean_distance(&middle) < 1e-3);
+        }
+        assert!(map.grid(view, GridMode::Hidden).is_none());
+
+        //hidden layers don't count
+        for i in 0..map.layer_count() {
+            map.layers[i].visible = false;
+        }
+        let inside = map.grid(view, GridMode::InsideOnly).unwrap();
+        assert!(inside.lines().is_empty());
+    }
+
+    #[test]
+    fn move_undo_redo() {
+        let mut map = test_map();
+        let mut history: History<_> = History::new();
+        let room = |x: f64| {
+            MultiPolygon::new(vec![polygon![
+                (x: x, y: 0.),
+                (x: x, y: 50.),
+                (x: x + 50., y: 50.),
+                (x: x + 50., y: 0.)
+            ]])
+        };
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, room(0.))));
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, room(200.))));
+        let before = map.layers[0].area().unsigned_area();
+        //the first room is moved so it overlaps the second
+        history.edit(
+            &mut map,
+            MapEdit::Move(MapMove::new(0, room(0.), coord! {x: 175., y: 0.})),
+        );
+        let area = map.layers[0].area();
+        assert_eq!(area.0.len(), 1);
+        assert_eq!(area.unsigned_area(), 75. * 50.);
+        assert!(map.layers[0].hatching_stale());
+        //undoing puts both rooms back whole
+        history.undo(&mut map);
+        let area = map.layers[0].area();
+        assert_eq!(area.0.len(), 2);
+        assert_eq!(area.unsigned_area(), before);
+        assert!(area.contains(&Point::new(25., 25.)));
+        assert!(area.contains(&Point::new(210., 25.)));
+        history.redo(&mut map);
+        assert_eq!(map.layers[0].area().unsigned_area(), 75. * 50.);
+    }
+
+    #[test]
+    fn reshape_undo_redo() {
+        let mut map = test_map();
+        let mut history: History<_> = History::new();
+        let square = map.layers[1].area().0[0].clone();
+        //a point pulled up out of the top edge
+        let peaked = polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 100.),
+            (x: 50., y: 150.),
+            (x: 100., y: 100.),
+            (x: 100., y: 0.)
+        ];
+        history.edit(
+            &mut map,
+            MapEdit::Reshape(MapReshape::new(1, square.clone(), peaked)),
+        );
+        assert_eq!(map.layers[1].area().unsigned_area(), 100. * 100. + 2500.);
+        assert_eq!(map.layers[1].area_sketch().lines().len(), 5);
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].area().unsigned_area(), 100. * 100.);
+        assert_eq!(map.layers[1].area_sketch().lines().len(), 4);
+        history.redo(&mut map);
+        assert_eq!(map.layers[1].area().0[0].exterior().0.len(), 6);
+    }
+
+    #[test]
+    fn style_edits_merge() {
+        let mut map = test_map();
+        let mut history: History<_> = History::new();
+        for thickness in [4.0, 5.0, 6.0] {
+            let style = LayerStyle {
+                outline_thickness: thickness,
+                ..LayerStyle::default()
+            };
+            let edit = MapStyleLayer::new(1, style).with_gesture(Some(1));
+            history.edit(&mut map, MapEdit::StyleLayer(edit));
+        }
+        assert_eq!(map.layers[1].style().outline_thickness, 6.0);
+        //a whole slider drag is one undo step
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].style(), &LayerStyle::default());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn separate_style_edits() {
+        let mut map = test_map();
+        let mut history: History<_> = History::new();
+        let thicker = LayerStyle {
+            outline_thickness: 6.0,
+            ..LayerStyle::default()
+        };
+        let black = LayerStyle {
+            floor_color: BLACK,
+            ..thicker
+        };
+        //two drags, then a change with no gesture at all
+        history.edit(
+            &mut map,
+            MapEdit::StyleLayer(MapStyleLayer::new(1, thicker).with_gesture(Some(1))),
+        );
+        history.edit(
+            &mut map,
+            MapEdit::StyleLayer(MapStyleLayer::new(1, black).with_gesture(Some(2))),
+        );
+        history.edit(
+            &mut map,
+            MapEdit::StyleLayer(MapStyleLayer::new(1, LayerStyle::default())),
+        );
+        history.edit(
+            &mut map,
+            MapEdit::StyleLayer(MapStyleLayer::new(1, thicker)),
+        );
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].style(), &LayerStyle::default());
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].style(), &black);
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].style(), &thicker);
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].style(), &LayerStyle::default());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn hatch_edits_merge_per_drag() {
+        let mut map = test_map();
+        let mut history: History<_> = History::new();
+        let hatch = |offset| HatchStyle {
+            offset,
+            ..HatchStyle::default()
+        };
+        for (offset, gesture) in [(40., 1), (60., 1), (80., 2)] {
+            let edit = MapHatchLayer::new(1, hatch(offset)).with_gesture(Some(gesture));
+            history.edit(&mut map, MapEdit::HatchLayer(edit));
+        }
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].hatch(), &hatch(60.));
+        history.undo(&mut map);
+        assert_eq!(map.layers[1].hatch(), &HatchStyle::default());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn draw_order() {
+        struct Recorder(Vec<u64>);
+        impl Canvas for Recorder {
+            fn fill(&mut self, fill: &Fill) {
+                self.0.push(fill.revision());
+            }
+            fn sketch(&mut self, sketch: &Sketch) {
+                self.0.push(sketch.revision());
+            }
+        }
+        let mut map = test_map();
+        map.layers[0].visible = false;
+        let grid = Sketch::new(1., GRAY);
+        let mut recorder = Recorder(vec![]);
+        map.draw(Some(&grid), &mut recorder);
+        let l = &map.layers[1];
+        let expected = [
+            l.floor().revision(),
+            grid.revision(),
+            l.shading().revision(),
+            l.hatching().revision(),
+            l.area_sketch().revision(),
+        ];
+        assert_eq!(recorder.0, expected);
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use geo::{coord, BooleanOps, Coord, LinesIter};
+use serde::{Deserialize, Serialize};
+
+//Plain RGBA color, so the model doesn't depend on any particular renderer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[f32; 4]", into = "[f32; 4]")]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color { r, g, b, a }
+    }
+}
+
+impl From<[f32; 4]> for Color {
+    fn from(c: [f32; 4]) -> Self {
+        Color::new(c[0], c[1], c[2], c[3])
+    }
+}
+
+impl From<Color> for [f32; 4] {
+    fn from(c: Color) -> Self {
+        [c.r, c.g, c.b, c.a]
+    }
+}
+
+//same values as the macroquad colors of the same name
+pub const WHITE: Color = Color::new(1.00, 1.00, 1.00, 1.00);
+pub const BLACK: Color = Color::new(0.00, 0.00, 0.00, 1.00);
+pub const GRAY: Color = Color::new(0.51, 0.51, 0.51, 1.00);
+pub const LIGHTGRAY: Color = Color::new(0.78, 0.78, 0.78, 1.00);
+pub const RED: Color = Color::new(0.90, 0.16, 0.22, 1.00);
+
+#[derive(Debug, Clone)]
+pub struct Sketch {
+    lines: Vec<Line>,
+    thickness: f32,
+    color: Color,
+    revision: u64,
+}
+
+//Every change to a sketch or fill gets a new revision, unique across all of them,
+//so renderers can cache whatever they build from one until it changes
+fn next_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+impl PartialEq for Sketch {
+    fn eq(&self, other: &Self) -> bool {
+        self.lines == other.lines && self.thickness == other.thickness && self.color == other.color
+    }
+}
+
+impl Sketch {
+    pub fn new(thickness: f32, color: Color) -> Self {
+        Sketch {
+            lines: Vec::new(),
+            thickness,
+            color,
+            revision: next_revision(),
+        }
+    }
+    pub fn add(&mut self, line: Line) {
+        self.lines.push(line);
+        self.revision = next_revision();
+    }
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.revision = next_revision();
+    }
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+    pub fn thickness(&self) -> f32 {
+        self.thickness
+    }
+    pub fn color(&self) -> Color {
+        self.color
+    }
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    pub fn set_stroke(&mut self, thickness: f32, color: Color) {
+        self.thickness = thickness;
+        self.color = color;
+        self.revision = next_revision();
+    }
+}
+
+//Solid areas, like shaded hatching. Renderers that can only draw triangles
+//have to triangulate the polygons themselves.
+#[derive(Debug, Clone)]
+pub struct Fill {
+    polygons: Vec<geo::Polygon<f32>>,
+    color: Color,
+    revision: u64,
+}
+
+impl PartialEq for Fill {
+    fn eq(&self, other: &Self) -> bool {
+        self.polygons == other.polygons && self.color == other.color
+    }
+}
+
+impl Fill {
+    pub fn new(color: Color) -> Self {
+        Fill {
+            polygons: Vec::new(),
+            color,
+            revision: next_revision(),
+        }
+    }
+    pub fn add(&mut self, polygon: geo::Polygon<f32>) {
+        self.polygons.push(polygon);
+        self.revision = next_revision();
+    }
+    pub fn clear(&mut self) {
+        self.polygons.clear();
+        self.revision = next_revision();
+    }
+    pub fn polygons(&self) -> &[geo::Polygon<f32>] {
+        &self.polygons
+    }
+    pub fn color(&self) -> Color {
+        self.color
+    }
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+        self.revision = next_revision();
+    }
+}
+
+//Anything a whole map gets drawn onto, see Map::draw. Lines are stroked with round caps,
+//like the circles the on-screen meshes put at each end, so every renderer looks the same.
+pub trait Canvas {
+    fn fill(&mut self, fill: &Fill);
+    fn sketch(&mut self, sketch: &Sketch);
+}
+
+//The parts of the sketch's lines that are inside area, with the same stroke
+pub fn clip_sketch(sketch: &Sketch, area: &geo::MultiPolygon) -> Sketch {
+    let lines = geo::MultiLineString::new(
+        sketch
+            .lines()
+            .iter()
+            .map(|l| {
+                let (start, end) = (l.start(), l.end());
+                geo::LineString::from(vec![
+                    (start.x as f64, start.y as f64),
+                    (end.x as f64, end.y as f64),
+                ])
+            })
+            .collect(),
+    );
+    let mut clipped = Sketch::new(sketch.thickness(), sketch.color());
+    for line in area.clip(&lines, false).lines_iter() {
+        clipped.add(Line::from_geo(line));
+    }
+    clipped
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line(Coord<f32>, Coord<f32>);
+
+impl Line {
+    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
+        Line(coord! {x: x1, y: y1}, coord! {x: x2, y: y2})
+    }
+    //should probably implement the From trait instead
+    pub fn from_geo(line: geo::Line) -> Self {
+        Line::new(
+            line.start.x as f32,
+            line.start.y as f32,
+            line.end.x as f32,
+            line.end.y as f32,
+        )
+    }
+    pub fn start(&self) -> Coord<f32> {
+        self.0
+    }
+    pub fn end(&self) -> Coord<f32> {
+        self.1
+    }
+}
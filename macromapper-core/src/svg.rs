@@ -0,0 +1,192 @@
+use std::fmt::Write;
+
+use crate::{grid::GridMode, map::*, sketch::*};
+use geo::coord;
+
+//Space left around the map, unless some layer's hatching reaches further
+const EXPORT_MARGIN: f32 = 100.;
+
+//World-space area covered by an export, with room for the widest hatching so it isn't cut
+//off at the edges
+pub fn export_rect(map: &Map) -> Option<geo::Rect<f32>> {
+    let bounds = map.bounding_rect()?;
+    let margin = map
+        .visible_layers()
+        .map(|l| l.hatch().reach() as f32 + l.style().hatch_thickness)
+        .fold(EXPORT_MARGIN, f32::max);
+    Some(geo::Rect::new(
+        coord! {
+            x: bounds.min().x as f32 - margin,
+            y: bounds.min().y as f32 - margin,
+        },
+        coord! {
+            x: bounds.max().x as f32 + margin,
+            y: bounds.max().y as f32 + margin,
+        },
+    ))
+}
+
+//One world unit is one SVG user unit, with the y-axis flipped so up stays up
+pub fn map_to_svg(map: &Map, grid: GridMode) -> Option<String> {
+    let world = export_rect(map)?;
+    let mut svg = String::new();
+    //writing to a String can't fail, so the results are ignored throughout
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = world.width(),
+        h = world.height()
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    map.draw(
+        map.grid(world, grid).as_ref(),
+        &mut SvgCanvas {
+            svg: &mut svg,
+            world,
+        },
+    );
+    svg.push_str("</svg>\n");
+    Some(svg)
+}
+
+struct SvgCanvas<'a> {
+    svg: &'a mut String,
+    world: geo::Rect<f32>,
+}
+
+impl Canvas for SvgCanvas<'_> {
+    fn fill(&mut self, fill: &Fill) {
+        svg_fill(self.svg, fill, self.world);
+    }
+    fn sketch(&mut self, sketch: &Sketch) {
+        svg_sketch(self.svg, sketch, self.world);
+    }
+}
+
+//Like sketches, one path per fill. Holes are rings of their own, so even-odd filling cuts them out
+fn svg_fill(svg: &mut String, fill: &Fill, world: geo::Rect<f32>) {
+    if fill.polygons().is_empty() {
+        return;
+    }
+    let color = fill.color();
+    let _ = write!(svg, r#"<path d=""#);
+    for polygon in fill.polygons() {
+        for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+            for (i, point) in ring.points().enumerate() {
+                let _ = write!(
+                    svg,
+                    "{}{} {}",
+                    if i == 0 { "M" } else { "L" },
+                    point.x() - world.min().x,
+                    world.max().y - point.y()
+                );
+            }
+            svg.push('Z');
+        }
+    }
+    let _ = writeln!(
+        svg,
+        r#"" fill="rgb({},{},{})" fill-opacity="{}" fill-rule="evenodd"/>"#,
+        (color.r * 255.).round(),
+        (color.g * 255.).round(),
+        (color.b * 255.).round(),
+        color.a
+    );
+}
+
+//Each sketch becomes a single path, since all of its lines share a stroke
+fn svg_sketch(svg: &mut String, sketch: &Sketch, world: geo::Rect<f32>) {
+    if sketch.lines().is_empty() {
+        return;
+    }
+    let color = sketch.color();
+    let _ = write!(svg, r#"<path d=""#);
+    for line in sketch.lines() {
+        let (start, end) = (line.start(), line.end());
+        let _ = write!(
+            svg,
+            "M{} {}L{} {}",
+            start.x - world.min().x,
+            world.max().y - start.y,
+            end.x - world.min().x,
+            world.max().y - end.y
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"" fill="none" stroke="rgb({},{},{})" stroke-opacity="{}" stroke-width="{}" stroke-linecap="round"/>"#,
+        (color.r * 255.).round(),
+        (color.g * 255.).round(),
+        (color.b * 255.).round(),
+        color.a,
+        sketch.thickness()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hatching::HatchStyle;
+    use geo::polygon;
+    use undo::Record;
+
+    #[test]
+    fn svg_contains_every_sketch() {
+        let mut map = Map::new();
+        map.append_layer();
+        assert!(map_to_svg(&map, GridMode::Full).is_none());
+
+        let mut history = Record::<MapEdit>::new();
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(
+                0,
+                geo::MultiPolygon::new(vec![polygon![
+                    (x: 0., y: 0.),
+                    (x: 0., y: 100.),
+                    (x: 100., y: 100.),
+                    (x: 100., y: 0.)
+                ]]),
+            )),
+        );
+        map.regenerate_hatching();
+        let svg = map_to_svg(&map, GridMode::Full).unwrap();
+        assert!(svg.starts_with("<svg"));
+        //floor, grid, hatching and outline
+        assert_eq!(svg.matches("<path").count(), 4);
+        assert!(svg.contains(r#"stroke="rgb(0,0,0)" stroke-opacity="1" stroke-width="3""#));
+
+        let svg = map_to_svg(&map, GridMode::Hidden).unwrap();
+        assert_eq!(svg.matches("<path").count(), 3);
+    }
+
+    #[test]
+    fn wide_bands_fit_the_export() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let square = geo::MultiPolygon::new(vec![polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 100.),
+            (x: 100., y: 100.),
+            (x: 100., y: 0.)
+        ]]);
+        history.edit(&mut map, MapEdit::Union(MapUnion::new(0, square)));
+        let hatch = HatchStyle {
+            offset: 150.,
+            ..Default::default()
+        };
+        history.edit(&mut map, MapEdit::HatchLayer(MapHatchLayer::new(0, hatch)));
+        map.regenerate_hatching();
+
+        let world = export_rect(&map).unwrap();
+        let lines = map.layers_iter().next().unwrap().hatching().lines();
+        assert!(lines.iter().any(|l| l.start().x < -100.));
+        for l in lines {
+            for c in [l.start(), l.end()] {
+                assert!(world.min().x <= c.x && c.x <= world.max().x);
+                assert!(world.min().y <= c.y && c.y <= world.max().y);
+            }
+        }
+    }
+}
@@ -1,324 +1,189 @@
-use egui_macroquad::egui::util::hash;
-use egui_macroquad::macroquad::{
-    prelude::*,
-    rand::{rand, srand},
-};
-use fast_poisson::Poisson2D;
-use geo::line_intersection::line_intersection;
-use geo::*;
+use std::collections::{HashMap, HashSet};
+
+use egui_macroquad::macroquad::{models::Vertex, prelude::*};
+use geo::{coord, Coord, TriangulateEarcut};
+use macromapper_core::sketch::{self, Canvas, Fill, Line, Sketch};
 use new_egui_macroquad as egui_macroquad;
-use undo::*;
-use voronator::VoronoiDiagram;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Sketch {
-    lines: Vec<Line>,
-    thickness: f32,
-    color: Color,
-}
+//The map model is renderer-agnostic, so its types get converted here at the edge
 
-impl Sketch {
-    pub fn new(thickness: f32, color: Color) -> Self {
-        Sketch {
-            lines: Vec::new(),
-            thickness,
-            color,
-        }
-    }
-    pub fn add(&mut self, line: Line) {
-        self.lines.push(line);
-    }
-    pub fn draw(&self) {
-        self.lines
-            .iter()
-            .for_each(|i| i.draw(self.thickness, self.color))
-    }
-    pub fn clear(&mut self) {
-        self.lines.clear();
-    }
+pub fn mq_color(color: sketch::Color) -> Color {
+    Color::new(color.r, color.g, color.b, color.a)
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Line(Vec2, Vec2);
+pub fn mq_vec(point: Coord<f32>) -> Vec2 {
+    vec2(point.x, point.y)
+}
 
-impl Line {
-    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
-        Line(vec2(x1, y1), vec2(x2, y2))
-    }
-    //should probably implement the From trait instead
-    pub fn from_geo(line: geo::Line) -> Self {
-        Line(
-            vec2(line.start_point().x() as f32, line.start_point().y() as f32),
-            vec2(line.end_point().x() as f32, line.end_point().y() as f32),
-        )
-    }
-    pub fn draw(&self, thickness: f32, color: Color) {
-        draw_line(self.0.x, self.0.y, self.1.x, self.1.y, thickness, color);
-        draw_circle(self.0.x, self.0.y, thickness / 2., color);
-        draw_circle(self.1.x, self.1.y, thickness / 2., color);
-    }
+pub fn geo_coord(point: Vec2) -> Coord<f32> {
+    coord! {x: point.x, y: point.y}
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Layer {
-    area: MultiPolygon,
-    area_sketch: Sketch,
-    hatching: Sketch,
-    name: String,
+pub fn mq_rect(rect: geo::Rect<f32>) -> Rect {
+    Rect::new(rect.min().x, rect.min().y, rect.width(), rect.height())
 }
 
-impl Layer {
-    pub fn new(name: String) -> Self {
-        Layer {
-            area: MultiPolygon(vec![]),
-            area_sketch: Sketch::new(3.0, BLACK),
-            hatching: Sketch::new(2.0, GRAY),
-            name,
-        }
-    }
-    pub fn draw(&self) {
-        self.hatching.draw();
-        self.area_sketch.draw();
-    }
-    fn update_sketch(&mut self) {
-        self.area_sketch.clear();
-        for l in self.area.lines_iter() {
-            self.area_sketch.add(Line::from_geo(l));
-        }
-    }
-    fn generate_hatching(&mut self) {
-        self.hatching.clear();
-        let bounding_box = match self.area.bounding_rect() {
-            None => return,
-            Some(r) => r,
-        };
-        let radius = 20.;
-        let offset = 50.0;
-        let hatch_count = 10;
-        let points = Poisson2D::new()
-            .with_seed(0x5EED)
-            .with_dimensions(
-                [
-                    bounding_box.width() + 2.0 * offset,
-                    bounding_box.height() + 2.0 * offset,
-                ],
-                radius,
-            )
-            .iter()
-            .map(|point| -> (f64, f64) {
-                (
-                    point[0] + bounding_box.min().x - offset,
-                    point[1] + bounding_box.min().y - offset,
-                )
-            })
-            .collect::<Vec<(f64, f64)>>();
-        let voronoi: Vec<Polygon> = VoronoiDiagram::<voronator::delaunator::Point>::from_tuple(
-            &(bounding_box.min().x - offset, bounding_box.min().y - offset),
-            &(bounding_box.max().x + offset, bounding_box.max().y + offset),
-            &points,
-        )
-        .expect("points should give a valid voronoi diagram")
-        .cells()
-        .iter()
-        .map(|polygon| -> Polygon<f64> {
-            Polygon::new(
-                LineString::from(
-                    polygon
-                        .points()
-                        .iter()
-                        .map(|p| (p.x, p.y))
-                        .collect::<Vec<_>>(),
-                ),
-                vec![],
-            )
-        })
-        .filter(|polygon| {
-            let centroid = polygon
-                .centroid()
-                .expect("all polygons should have a centroid");
-            !self.area.contains(polygon) && self.area.euclidean_distance(&centroid) <= offset * 0.75
-        })
-        .map(|polygon| {
-            MultiPolygon::from(polygon)
-                .boolean_op(&self.area, OpType::Difference)
-                .into_iter()
-                .next()
-                .expect("should be exactly one")
-        })
-        .collect();
-        let hatches_base: Vec<geo::Line> = (-hatch_count..=hatch_count)
-            .map(|i| {
-                let x = radius * 4. * (i as f64) / (hatch_count as f64);
-                geo::Line::new(coord! {x: x, y: -radius * 2.}, coord! {x:x, y: radius * 2.})
-            })
-            .collect();
-        for polygon in voronoi {
-            let center = polygon
-                .centroid()
-                .expect("all polygons should have a centroid");
-            srand(hash((center.x() + center.y()).to_bits()));
-            let rot = rand() % 180;
-            let hatches: Vec<_> = hatches_base
-                .clone()
-                .into_iter()
-                .filter_map(|mut hatch| {
-                    hatch.translate_mut(center.x(), center.y());
-                    hatch.rotate_around_point_mut(rot as f64, center);
-                    let new_points = polygon
-                        .lines_iter()
-                        .filter_map(|line| line_intersection(hatch, line))
-                        .filter_map(|intersection| match intersection {
-                            LineIntersection::SinglePoint {
-                                intersection,
-                                is_proper: _,
-                            } => Some(intersection),
-                            _ => None,
-                        })
-                        .collect::<Vec<_>>();
-                    if new_points.len() != 2 {
-                        None
-                    } else {
-                        Some(geo::Line {
-                            start: new_points[0],
-                            end: new_points[1],
-                        })
-                    }
-                })
-                //This filter is aesthetic: short lines in the hatching look bad.
-                //However, it can lead to unwanted behavior: when a polygon is large, but cut to a
-                //short and squat area by the map geometry, and the hatches align, it can leave
-                //blank space in a large area.
-                //TODO: fix
-                .filter(|line| line.euclidean_length() > 7.)
-                .collect();
-            for line in hatches {
-                self.hatching.add(Line::from_geo(line));
-            }
-        }
-    }
+pub fn geo_rect(rect: Rect) -> geo::Rect<f32> {
+    geo::Rect::new(
+        coord! {x: rect.left(), y: rect.top()},
+        coord! {x: rect.right(), y: rect.bottom()},
+    )
 }
 
-#[derive(Clone)]
-pub struct Map {
-    layers: Vec<Layer>,
+pub trait Draw {
+    fn draw(&self);
 }
 
-impl Map {
-    pub fn new() -> Self {
-        Map { layers: vec![] }
-    }
-    pub fn append_layer(&mut self) {
-        self.layers.push(Layer::new(
-            "layer-".to_string() + &self.layers.len().to_string(),
-        ));
-    }
-    pub fn layers_iter(&self) -> core::slice::Iter<'_, Layer> {
-        self.layers.iter()
+//The grid and tool previews change every frame, so they get tessellated on the spot.
+//Anything that sticks around, like layers, goes through a MeshCache.
+impl Draw for Sketch {
+    fn draw(&self) {
+        sketch_meshes(self).iter().for_each(draw_mesh);
     }
 }
 
-pub enum MapEdit {
-    Union(MapUnion),
-    Subtraction(MapSubtraction),
+//A size readout next to the cursor, in screen space
+pub fn draw_measurement(text: &str, pos: Vec2) {
+    let dimensions = measure_text(text, None, 20, 1.);
+    draw_rectangle(
+        pos.x - 4.,
+        pos.y - dimensions.offset_y - 4.,
+        dimensions.width + 8.,
+        dimensions.height + 8.,
+        Color::new(1., 1., 1., 0.85),
+    );
+    draw_text(text, pos.x, pos.y, 20., BLACK);
 }
 
-//boring boilerplate to make things work
-//check edit and undo methods for each operation for implementation details
-impl Edit for MapEdit {
-    type Target = Map;
-    type Output = ();
+//Meshes built from sketches and fills, kept until the revision they were built from
+//changes. Hatching can run to thousands of lines, which is far too many draw calls.
+#[derive(Default)]
+pub struct MeshCache {
+    meshes: HashMap<u64, Vec<Mesh>>,
+    used: HashSet<u64>,
+}
 
-    fn edit(&mut self, target: &mut Self::Target) -> Self::Output {
-        match self {
-            MapEdit::Union(u) => u.edit(target),
-            MapEdit::Subtraction(s) => s.edit(target),
-        }
+impl MeshCache {
+    pub fn draw_sketch(&mut self, sketch: &Sketch) {
+        self.draw(sketch.revision(), || sketch_meshes(sketch));
     }
-    fn undo(&mut self, target: &mut Self::Target) -> Self::Output {
-        match self {
-            MapEdit::Union(u) => u.undo(target),
-            MapEdit::Subtraction(s) => s.undo(target),
-        }
+    pub fn draw_fill(&mut self, fill: &Fill) {
+        self.draw(fill.revision(), || fill_meshes(fill));
+    }
+    //Throws away the meshes that weren't drawn since the last call
+    pub fn end_frame(&mut self) {
+        let used = std::mem::take(&mut self.used);
+        self.meshes.retain(|revision, _| used.contains(revision));
+    }
+    fn draw(&mut self, revision: u64, build: impl FnOnce() -> Vec<Mesh>) {
+        self.used.insert(revision);
+        self.meshes
+            .entry(revision)
+            .or_insert_with(build)
+            .iter()
+            .for_each(draw_mesh);
     }
 }
 
-pub struct MapUnion {
-    base: MultiPolygon,
-    operator: MultiPolygon,
-    layer: usize,
-}
-
-impl MapUnion {
-    pub fn new(layer: usize, operator: MultiPolygon) -> Self {
-        Self {
-            base: MultiPolygon(vec![]),
-            operator,
-            layer,
-        }
-    }
-    fn edit(&mut self, target: &mut Map) {
-        let target_layer = target
-            .layers
-            .get_mut(self.layer)
-            .expect("layer should exist");
-        self.base = target_layer.area.intersection(&self.operator);
-        target_layer.area = target_layer.area.union(&self.operator);
-        target_layer.update_sketch();
-        target_layer.generate_hatching();
+impl Canvas for MeshCache {
+    fn fill(&mut self, fill: &Fill) {
+        self.draw_fill(fill);
     }
-    fn undo(&mut self, target: &mut Map) {
-        let target_layer = target
-            .layers
-            .get_mut(self.layer)
-            .expect("layer should exist");
-        target_layer.area = target_layer
-            .area
-            .difference(&self.operator)
-            .union(&self.base);
-        target_layer.update_sketch();
-        target_layer.generate_hatching();
+    fn sketch(&mut self, sketch: &Sketch) {
+        self.draw_sketch(sketch);
     }
 }
 
-pub struct MapSubtraction {
-    base: MultiPolygon,
-    operator: MultiPolygon,
-    layer: usize,
+//macroquad clamps any single piece of geometry bigger than its draw call buffers
+const MAX_MESH_VERTICES: usize = 10000;
+const MAX_MESH_INDICES: usize = 5000;
+//Round line caps, same as the circles draw_line used to get at each end
+const CAP_SIDES: u16 = 10;
+
+//Collects shapes into as few meshes as the buffer limits allow
+#[derive(Default)]
+struct MeshBuilder {
+    meshes: Vec<Mesh>,
 }
 
-impl MapSubtraction {
-    pub fn new(layer: usize, operator: MultiPolygon) -> Self {
-        Self {
-            base: MultiPolygon(vec![]),
-            operator,
-            layer,
+impl MeshBuilder {
+    fn shape(&mut self, points: &[Vec2], indices: &[u16], color: Color) {
+        let fits = self.meshes.last().is_some_and(|m| {
+            m.vertices.len() + points.len() < MAX_MESH_VERTICES
+                && m.indices.len() + indices.len() < MAX_MESH_INDICES
+        });
+        if !fits {
+            self.meshes.push(Mesh {
+                vertices: vec![],
+                indices: vec![],
+                texture: None,
+            });
+        }
+        let Some(mesh) = self.meshes.last_mut() else {
+            return;
+        };
+        let base = mesh.vertices.len() as u16;
+        mesh.vertices.extend(points.iter().map(|p| Vertex {
+            position: vec3(p.x, p.y, 0.),
+            uv: Vec2::ZERO,
+            color,
+        }));
+        mesh.indices.extend(indices.iter().map(|i| base + i));
+    }
+    fn line(&mut self, line: &Line, thickness: f32, color: Color) {
+        let (start, end) = (mq_vec(line.start()), mq_vec(line.end()));
+        let radius = thickness / 2.;
+        //zero length lines are dots, and only get their caps
+        if start != end {
+            let side = (end - start).normalize().perp() * radius;
+            self.shape(
+                &[start + side, start - side, end + side, end - side],
+                &[0, 1, 2, 1, 3, 2],
+                color,
+            );
+        }
+        for center in [start, end] {
+            let mut points = vec![center];
+            points.extend((0..CAP_SIDES).map(|i| {
+                let angle = i as f32 / CAP_SIDES as f32 * std::f32::consts::TAU;
+                center + Vec2::from_angle(angle) * radius
+            }));
+            let indices: Vec<u16> = (0..CAP_SIDES)
+                .flat_map(|i| [0, i + 1, (i + 1) % CAP_SIDES + 1])
+                .collect();
+            self.shape(&points, &indices, color);
         }
     }
-    fn edit(&mut self, target: &mut Map) {
-        let target_layer = target
-            .layers
-            .get_mut(self.layer)
-            .expect("layer should exist");
-        self.base = target_layer.area.intersection(&self.operator);
-        target_layer.area = target_layer.area.difference(&self.operator);
-        target_layer.update_sketch();
-        target_layer.generate_hatching();
-    }
-    fn undo(&mut self, target: &mut Map) {
-        let target_layer = target
-            .layers
-            .get_mut(self.layer)
-            .expect("layer should exist");
-        target_layer.area = target_layer.area.union(&self.base);
-        target_layer.update_sketch();
-        target_layer.generate_hatching();
+}
+
+fn sketch_meshes(sketch: &Sketch) -> Vec<Mesh> {
+    let mut builder = MeshBuilder::default();
+    let color = mq_color(sketch.color());
+    for line in sketch.lines() {
+        builder.line(line, sketch.thickness(), color);
     }
+    builder.meshes
 }
 
-#[derive(Clone)]
-pub enum PolyOpType {
-    Union,
-    Subtraction,
+fn fill_meshes(fill: &Fill) -> Vec<Mesh> {
+    let mut builder = MeshBuilder::default();
+    let color = mq_color(fill.color());
+    for triangle in fill
+        .polygons()
+        .iter()
+        .flat_map(|p| p.earcut_triangles_iter())
+    {
+        builder.shape(
+            &[
+                mq_vec(triangle.v1()),
+                mq_vec(triangle.v2()),
+                mq_vec(triangle.v3()),
+            ],
+            &[0, 1, 2],
+            color,
+        );
+    }
+    builder.meshes
 }
 
 #[cfg(test)]
@@ -326,76 +191,26 @@ mod tests {
     use super::*;
 
     #[test]
-    fn basic_undo_redo() {
-        let mut test_map: Map = Map {
-            layers: vec![Layer::new("test".to_string())],
-        };
-        let mut history: History<_> = History::new();
-        history.edit(
-            &mut test_map,
-            MapEdit::Union(MapUnion::new(
-                0,
-                MultiPolygon::new(vec![polygon![
-                    (x: 0., y: 0.),
-                    (x: 0., y: 1.),
-                    (x: 1., y: 1.),
-                    (x: 1., y: 0.)
-                ]]),
-            )),
-        );
-        let one = test_map.clone();
-        history.edit(
-            &mut test_map,
-            MapEdit::Union(MapUnion::new(
-                0,
-                MultiPolygon::new(vec![polygon![
-                    (x: 1., y: 0.),
-                    (x: 1., y: 0.5),
-                    (x: 2., y: 0.5),
-                    (x: 2., y: 0.)
-                ]]),
-            )),
-        );
-        let two = test_map.clone();
-        history.undo(&mut test_map);
-        let three = test_map.clone();
-        history.redo(&mut test_map);
-        let four = test_map.clone();
-        history.edit(
-            &mut test_map,
-            MapEdit::Union(MapUnion::new(
-                0,
-                MultiPolygon::new(vec![polygon![
-                    (x: 0., y: 0.),
-                    (x: 0., y: 1.),
-                    (x: 1., y: 1.),
-                    (x: 1., y: 0.)
-                ]]),
-            )),
-        );
-        let five = test_map.clone();
-        history.undo(&mut test_map);
-        let six = test_map.clone();
-        history.redo(&mut test_map);
-        let seven = test_map.clone();
-
-        //Points are different so comparing whole layers would fail even if behavior is correct
-        //I compare areas to ensure that the geometry makes sense without requiring the internals to be the same
-        assert_eq!(
-            one.layers.get(0).unwrap().area.unsigned_area(),
-            three.layers.get(0).unwrap().area.unsigned_area()
-        );
-        assert_eq!(
-            two.layers.get(0).unwrap().area.unsigned_area(),
-            four.layers.get(0).unwrap().area.unsigned_area()
-        );
-        assert_eq!(
-            four.layers.get(0).unwrap().area.unsigned_area(),
-            six.layers.get(0).unwrap().area.unsigned_area()
-        );
-        assert_eq!(
-            five.layers.get(0).unwrap().area.unsigned_area(),
-            seven.layers.get(0).unwrap().area.unsigned_area()
-        );
+    fn big_sketches_are_split_into_meshes() {
+        let mut sketch = Sketch::new(2., sketch::BLACK);
+        for i in 0..1000 {
+            sketch.add(Line::new(i as f32, 0., i as f32, 10.));
+        }
+        //a dot still gets drawn
+        sketch.add(Line::new(0., 0., 0., 0.));
+        let meshes = sketch_meshes(&sketch);
+        assert!(meshes.len() > 1);
+        for mesh in &meshes {
+            assert!(mesh.vertices.len() < MAX_MESH_VERTICES);
+            assert!(mesh.indices.len() < MAX_MESH_INDICES);
+            assert!(mesh
+                .indices
+                .iter()
+                .all(|&i| (i as usize) < mesh.vertices.len()));
+        }
+        let quads = 1000 * 6;
+        let caps = 1001 * 2 * CAP_SIDES as usize * 3;
+        let indices: usize = meshes.iter().map(|m| m.indices.len()).sum();
+        assert_eq!(indices, quads + caps);
     }
 }
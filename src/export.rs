@@ -0,0 +1,475 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use crate::drawing::*;
+use egui_macroquad::macroquad::prelude::*;
+use macromapper_core::{
+    grid::GridMode,
+    map::Map,
+    sketch::{Canvas, Fill, Sketch},
+    svg::*,
+};
+use new_egui_macroquad as egui_macroquad;
+use printpdf as pdf;
+//Render targets bigger than this aren't supported everywhere, so big exports are drawn in tiles
+const TILE_SIZE: u32 = 2048;
+//Most printers can't print right up to the edge of the paper
+const PAGE_MARGIN: f32 = 10.;
+const MM_PER_INCH: f32 = 25.4;
+const PT_PER_MM: f32 = 72. / MM_PER_INCH;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Png,
+    Svg,
+    Pdf,
+}
+
+impl ExportFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "PNG image",
+            ExportFormat::Svg => "SVG image",
+            ExportFormat::Pdf => "PDF document",
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Svg => "svg",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Paper {
+    Letter,
+    A4,
+}
+
+impl Paper {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Paper::Letter => "Letter",
+            Paper::A4 => "A4",
+        }
+    }
+    //portrait width and height in millimeters
+    pub fn size(&self) -> (f32, f32) {
+        match self {
+            Paper::Letter => (215.9, 279.4),
+            Paper::A4 => (210., 297.),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ExportSettings {
+    pub pixels_per_square: f32,
+    pub grid: GridMode,
+    pub paper: Paper,
+    pub inches_per_square: f32,
+    //how far neighboring pages repeat each other's edges, in millimeters
+    pub overlap: f32,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            pixels_per_square: 50.,
+            grid: GridMode::Full,
+            paper: Paper::Letter,
+            inches_per_square: 1.,
+            overlap: 10.,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    EmptyMap,
+    Io(io::Error),
+    Image(image::ImageError),
+    Pdf(pdf::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::EmptyMap => write!(f, "there is nothing on the map to export"),
+            ExportError::Io(e) => write!(f, "could not write file: {e}"),
+            ExportError::Image(e) => write!(f, "could not write image: {e}"),
+            ExportError::Pdf(e) => write!(f, "could not write PDF: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(e: image::ImageError) -> Self {
+        ExportError::Image(e)
+    }
+}
+
+impl From<pdf::Error> for ExportError {
+    fn from(e: pdf::Error) -> Self {
+        ExportError::Pdf(e)
+    }
+}
+
+//Renders the map offscreen, so the output doesn't depend on the window or the camera.
+//This changes the active camera, so call it before the main camera is set for the frame.
+pub fn export_png(map: &Map, path: &Path, settings: &ExportSettings) -> Result<(), ExportError> {
+    let world = export_rect(map).ok_or(ExportError::EmptyMap)?;
+    let grid_size = map.grid_settings().size;
+    let grid = map.grid(world, settings.grid);
+    let world = mq_rect(world);
+    let scale = settings.pixels_per_square / grid_size;
+    let width = (world.w * scale).ceil() as u32;
+    let height = (world.h * scale).ceil() as u32;
+
+    let mut bytes = vec![0u8; width as usize * height as usize * 4];
+    //every tile draws the whole map, so it's only tessellated once
+    let mut meshes = MeshCache::default();
+    for tile_y in (0..height).step_by(TILE_SIZE as usize) {
+        for tile_x in (0..width).step_by(TILE_SIZE as usize) {
+            let tile_w = TILE_SIZE.min(width - tile_x);
+            let tile_h = TILE_SIZE.min(height - tile_y);
+            //image rows go down from the top, but the world's y-axis points up
+            let tile_rect = Rect::new(
+                world.x + tile_x as f32 / scale,
+                world.bottom() - (tile_y + tile_h) as f32 / scale,
+                tile_w as f32 / scale,
+                tile_h as f32 / scale,
+            );
+            let target = render_target(tile_w, tile_h);
+            set_camera(&Camera2D {
+                target: tile_rect.center(),
+                zoom: vec2(2. / tile_rect.w, 2. / tile_rect.h),
+                render_target: Some(target.clone()),
+                ..Default::default()
+            });
+            draw_rectangle(tile_rect.x, tile_rect.y, tile_rect.w, tile_rect.h, WHITE);
+            map.draw(grid.as_ref(), &mut meshes);
+            //flushes the draw calls into the render target
+            set_default_camera();
+
+            //pixels come back bottom row first
+            let tile = target.texture.get_texture_data();
+            for row in 0..tile_h as usize {
+                let src = row * tile_w as usize * 4;
+                let dst_row = (tile_y + tile_h) as usize - row - 1;
+                let dst = (dst_row * width as usize + tile_x as usize) * 4;
+                bytes[dst..dst + tile_w as usize * 4]
+                    .copy_from_slice(&tile.bytes[src..src + tile_w as usize * 4]);
+            }
+        }
+    }
+    image::save_buffer(path, &bytes, width, height, image::ColorType::Rgba8)?;
+    Ok(())
+}
+
+pub fn export_svg(map: &Map, path: &Path, settings: &ExportSettings) -> Result<(), ExportError> {
+    let svg = map_to_svg(map, settings.grid).ok_or(ExportError::EmptyMap)?;
+    fs::write(path, svg)?;
+    Ok(())
+}
+
+//How a map is split into pages: the printable part of each page is a tile of the map,
+//and each tile starts one step after the previous, so that they overlap
+pub struct PageLayout {
+    pub columns: u32,
+    pub rows: u32,
+    mm_per_unit: f32,
+    printable: Vec2,
+    step: Vec2,
+}
+
+pub fn page_layout(world: Rect, grid_size: f32, settings: &ExportSettings) -> PageLayout {
+    let (page_w, page_h) = settings.paper.size();
+    let printable = vec2(page_w, page_h) - 2. * PAGE_MARGIN;
+    let overlap = settings.overlap.clamp(0., printable.min_element() / 2.);
+    let step = printable - overlap;
+    let mm_per_unit = settings.inches_per_square * MM_PER_INCH / grid_size;
+    let size = vec2(world.w, world.h) * mm_per_unit;
+    let pages = ((size - overlap) / step).ceil().max(Vec2::ONE);
+    PageLayout {
+        columns: pages.x as u32,
+        rows: pages.y as u32,
+        mm_per_unit,
+        printable,
+        step,
+    }
+}
+
+//Prints the map at a physical scale, tiled across as many pages as it needs
+pub fn export_pdf(map: &Map, path: &Path, settings: &ExportSettings) -> Result<(), ExportError> {
+    let world = export_rect(map).ok_or(ExportError::EmptyMap)?;
+    let grid_size = map.grid_settings().size;
+    let grid = map.grid(world, settings.grid);
+    let world = mq_rect(world);
+    let layout = page_layout(world, grid_size, settings);
+    let mm = layout.mm_per_unit;
+    let (page_w, page_h) = settings.paper.size();
+    let (doc, first_page, first_layer) =
+        pdf::PdfDocument::new("Macromapper map", pdf::Mm(page_w), pdf::Mm(page_h), "Map");
+    let font = doc.add_builtin_font(pdf::BuiltinFont::Helvetica)?;
+
+    for row in 0..layout.rows {
+        for column in 0..layout.columns {
+            let (page, layer) = if row == 0 && column == 0 {
+                (first_page, first_layer)
+            } else {
+                doc.add_page(pdf::Mm(page_w), pdf::Mm(page_h), "Map")
+            };
+            let layer = doc.get_page(page).get_layer(layer);
+            //rows count down from the top of the map
+            let tile = Rect::new(
+                world.x + column as f32 * layout.step.x / mm,
+                world.bottom() - (row as f32 * layout.step.y + layout.printable.y) / mm,
+                layout.printable.x / mm,
+                layout.printable.y / mm,
+            );
+            let to_page = |p: Vec2| {
+                pdf::Point::new(
+                    pdf::Mm(PAGE_MARGIN + (p.x - tile.x) * mm),
+                    pdf::Mm(PAGE_MARGIN + (p.y - tile.y) * mm),
+                )
+            };
+
+            layer.set_line_cap_style(pdf::LineCapStyle::Round);
+            map.draw(
+                grid.as_ref(),
+                &mut PdfCanvas {
+                    layer: &layer,
+                    tile,
+                    mm,
+                    to_page: &to_page,
+                },
+            );
+
+            pdf_marks(&layer, &layout, row, column);
+            layer.use_text(
+                format!(
+                    "Row {} of {}, column {} of {}",
+                    row + 1,
+                    layout.rows,
+                    column + 1,
+                    layout.columns
+                ),
+                8.,
+                pdf::Mm(PAGE_MARGIN),
+                pdf::Mm(PAGE_MARGIN / 3.),
+                &font,
+            );
+        }
+    }
+    doc.save(&mut BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+struct PdfCanvas<'a, F> {
+    layer: &'a pdf::PdfLayerReference,
+    tile: Rect,
+    mm: f32,
+    to_page: &'a F,
+}
+
+impl<F: Fn(Vec2) -> pdf::Point> Canvas for PdfCanvas<'_, F> {
+    fn fill(&mut self, fill: &Fill) {
+        pdf_fill(self.layer, fill, self.tile, self.to_page);
+    }
+    fn sketch(&mut self, sketch: &Sketch) {
+        pdf_sketch(self.layer, sketch, self.tile, self.mm, self.to_page);
+    }
+}
+
+fn pdf_sketch(
+    layer: &pdf::PdfLayerReference,
+    sketch: &Sketch,
+    tile: Rect,
+    mm: f32,
+    to_page: &impl Fn(Vec2) -> pdf::Point,
+) {
+    let color = sketch.color();
+    layer.set_outline_color(pdf::Color::Rgb(pdf::Rgb::new(
+        color.r, color.g, color.b, None,
+    )));
+    layer.set_outline_thickness(sketch.thickness() * mm * PT_PER_MM);
+    for line in sketch.lines() {
+        if let Some((start, end)) = clip_line(mq_vec(line.start()), mq_vec(line.end()), tile) {
+            layer.add_line(pdf::Line {
+                points: vec![(to_page(start), false), (to_page(end), false)],
+                is_closed: false,
+            });
+        }
+    }
+}
+
+//Fills aren't clipped by the printable area like lines are, so they're cut to the tile first
+fn pdf_fill(
+    layer: &pdf::PdfLayerReference,
+    fill: &Fill,
+    tile: Rect,
+    to_page: &impl Fn(Vec2) -> pdf::Point,
+) {
+    let color = fill.color();
+    layer.set_fill_color(pdf::Color::Rgb(pdf::Rgb::new(
+        color.r, color.g, color.b, None,
+    )));
+    let tile = geo::MultiPolygon::from(geo_rect(tile).to_polygon());
+    for polygon in fill.polygons() {
+        for polygon in
+            geo::BooleanOps::intersection(&tile, &geo::MultiPolygon::from(polygon.clone()))
+        {
+            let rings = std::iter::once(polygon.exterior())
+                .chain(polygon.interiors())
+                .map(|ring| {
+                    ring.points()
+                        .map(|p| (to_page(vec2(p.x(), p.y())), false))
+                        .collect()
+                })
+                .collect();
+            layer.add_polygon(pdf::Polygon {
+                rings,
+                mode: pdf::path::PaintMode::Fill,
+                winding_order: pdf::path::WindingOrder::EvenOdd,
+            });
+        }
+    }
+}
+
+//Crop marks at the corners of the printable area, and crosshairs in the middle of every
+//overlapping strip. The crosshairs land on the same spot of the map on both pages,
+//so they can be lined up on top of each other when taping the pages together.
+fn pdf_marks(layer: &pdf::PdfLayerReference, layout: &PageLayout, row: u32, column: u32) {
+    let mark = 4.;
+    let (left, bottom) = (PAGE_MARGIN, PAGE_MARGIN);
+    let (right, top) = (left + layout.printable.x, bottom + layout.printable.y);
+    let overlap = layout.printable - layout.step;
+    let mut lines = vec![];
+    for (x, dx) in [(left, -mark), (right, mark)] {
+        for (y, dy) in [(bottom, -mark), (top, mark)] {
+            lines.push(((x, y + dy), (x, y + dy * 2.)));
+            lines.push(((x + dx, y), (x + dx * 2., y)));
+        }
+    }
+    let mut crosshairs = vec![];
+    for y in [
+        bottom + layout.printable.y / 4.,
+        top - layout.printable.y / 4.,
+    ] {
+        if column > 0 {
+            crosshairs.push((left + overlap.x / 2., y));
+        }
+        if column + 1 < layout.columns {
+            crosshairs.push((right - overlap.x / 2., y));
+        }
+    }
+    for x in [
+        left + layout.printable.x / 4.,
+        right - layout.printable.x / 4.,
+    ] {
+        if row > 0 {
+            crosshairs.push((x, top - overlap.y / 2.));
+        }
+        if row + 1 < layout.rows {
+            crosshairs.push((x, bottom + overlap.y / 2.));
+        }
+    }
+    for (x, y) in crosshairs {
+        lines.push(((x - mark, y), (x + mark, y)));
+        lines.push(((x, y - mark), (x, y + mark)));
+    }
+
+    layer.set_outline_color(pdf::Color::Rgb(pdf::Rgb::new(1., 0., 0., None)));
+    layer.set_outline_thickness(0.5);
+    for ((x1, y1), (x2, y2)) in lines {
+        layer.add_line(pdf::Line {
+            points: vec![
+                (pdf::Point::new(pdf::Mm(x1), pdf::Mm(y1)), false),
+                (pdf::Point::new(pdf::Mm(x2), pdf::Mm(y2)), false),
+            ],
+            is_closed: false,
+        });
+    }
+}
+
+//Liang-Barsky line clipping, so lines don't spill out of a page's tile into its margins
+fn clip_line(start: Vec2, end: Vec2, rect: Rect) -> Option<(Vec2, Vec2)> {
+    let d = end - start;
+    let (mut t0, mut t1) = (0f32, 1f32);
+    for (p, q) in [
+        (-d.x, start.x - rect.left()),
+        (d.x, rect.right() - start.x),
+        (-d.y, start.y - rect.top()),
+        (d.y, rect.bottom() - start.y),
+    ] {
+        if p == 0. {
+            if q < 0. {
+                return None;
+            }
+        } else if p < 0. {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    (t0 <= t1).then(|| (start + d * t0, start + d * t1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pdf_page_layout() {
+        let settings = ExportSettings::default();
+        //a map a bit smaller than one letter page at an inch per square
+        let world = Rect::new(0., 0., 350., 450.);
+        let layout = page_layout(world, 50., &settings);
+        assert_eq!((layout.columns, layout.rows), (1, 1));
+
+        //just over one page wide needs a second column, but no more rows
+        let world = Rect::new(0., 0., 400., 450.);
+        let layout = page_layout(world, 50., &settings);
+        assert_eq!((layout.columns, layout.rows), (2, 1));
+
+        //half an inch per square fits it on one page again
+        let layout = page_layout(
+            world,
+            50.,
+            &ExportSettings {
+                inches_per_square: 0.5,
+                ..settings
+            },
+        );
+        assert_eq!((layout.columns, layout.rows), (1, 1));
+    }
+
+    #[test]
+    fn line_clipping() {
+        let rect = Rect::new(0., 0., 10., 10.);
+        assert_eq!(
+            clip_line(vec2(-5., 5.), vec2(15., 5.), rect),
+            Some((vec2(0., 5.), vec2(10., 5.)))
+        );
+        assert_eq!(
+            clip_line(vec2(2., 2.), vec2(3., 3.), rect),
+            Some((vec2(2., 2.), vec2(3., 3.)))
+        );
+        assert_eq!(clip_line(vec2(-5., -5.), vec2(-1., 20.), rect), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{fs, path::PathBuf};
+
+use macromapper_core::{file::*, map::*};
+use undo::Record;
+
+//Seconds between autosaves while there are unsaved changes
+pub const AUTOSAVE_INTERVAL: f64 = 30.;
+
+//Native dialogs block until the user picks something, which is fine for a desktop app.
+//The browser has no synchronous file dialog, so the web build just skips them for now.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn pick_open_path() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter("Macromapper map", &[FILE_EXTENSION])
+        .pick_file()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn pick_save_path() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter("Macromapper map", &[FILE_EXTENSION])
+        .set_file_name(format!("untitled.{FILE_EXTENSION}"))
+        .save_file()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn pick_export_path(description: &str, extension: &str) -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter(description, &[extension])
+        .set_file_name(format!("untitled.{extension}"))
+        .save_file()
+}
+
+//The recovery file only exists while there are unsaved changes, or after a crash.
+//Finding it at startup means the last session didn't exit cleanly.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn recovery_path() -> Option<PathBuf> {
+    Some(
+        dirs::data_local_dir()?
+            .join("macromapper")
+            .join(format!("recovery.{FILE_EXTENSION}")),
+    )
+}
+
+//Saves to path, asking where if there isn't one. Ok(None) means the dialog was cancelled
+pub fn save_or_ask(
+    path: Option<PathBuf>,
+    map: &Map,
+    history: &mut Record<MapEdit>,
+) -> Result<Option<PathBuf>, FileError> {
+    let Some(path) = path.or_else(pick_save_path) else {
+        return Ok(None);
+    };
+    save_map(&path, map, history)?;
+    history.set_saved(true);
+    Ok(Some(path))
+}
+
+pub fn autosave(map: &Map, history: &Record<MapEdit>) -> Result<(), FileError> {
+    let Some(path) = recovery_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    save_map(&path, map, history)
+}
+
+pub fn clear_recovery() {
+    if let Some(path) = recovery_path() {
+        //most of the time there's no file to remove, which is fine
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn pick_open_path() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn pick_save_path() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn pick_export_path(_description: &str, _extension: &str) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn recovery_path() -> Option<PathBuf> {
+    None
+}
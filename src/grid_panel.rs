@@ -0,0 +1,64 @@
+use crate::utils::changed_gesture;
+use egui_macroquad::egui;
+use macromapper_core::{
+    grid::{GridKind, GridMode, GridSettings},
+    map::*,
+};
+use new_egui_macroquad as egui_macroquad;
+
+//Like the layer panel, changes to the map's grid come back as an edit. That includes how much
+//of it is shown, so the map opens the way it was left
+pub fn grid_panel(ui: &mut egui::Ui, map: &Map) -> Option<MapEdit> {
+    let mut grid = map.grid_settings().clone();
+    egui::ComboBox::from_label("Grid shape")
+        .selected_text(grid.kind.name())
+        .show_ui(ui, |ui| {
+            for kind in GridKind::ALL {
+                ui.selectable_value(&mut grid.kind, kind, kind.name());
+            }
+        });
+    let mut responses =
+        vec![ui.add(egui::Slider::new(&mut grid.size, 10.0..=200.0).text("Cell size"))];
+    let preset = GridSettings::PRESETS
+        .iter()
+        .find(|(distance, unit)| *distance == grid.distance && *unit == grid.unit);
+    egui::ComboBox::from_label("Scale")
+        .selected_text(preset.map_or("Custom".to_string(), |(d, u)| format!("{d} {u}")))
+        .show_ui(ui, |ui| {
+            for (distance, unit) in GridSettings::PRESETS {
+                if ui
+                    .selectable_label(
+                        preset == Some(&(distance, unit)),
+                        format!("{distance} {unit}"),
+                    )
+                    .clicked()
+                {
+                    grid.distance = distance;
+                    grid.unit = unit.to_string();
+                }
+            }
+        });
+    //presets only fill these in, any other scale can be typed here
+    ui.horizontal(|ui| {
+        ui.label("One cell is");
+        responses.push(
+            ui.add(
+                egui::DragValue::new(&mut grid.distance)
+                    .speed(0.1)
+                    .range(0.01..=f32::MAX),
+            ),
+        );
+        responses.push(ui.add(egui::TextEdit::singleline(&mut grid.unit).desired_width(40.)));
+    });
+    egui::ComboBox::from_label("Show grid")
+        .selected_text(grid.mode.name())
+        .show_ui(ui, |ui| {
+            for mode in GridMode::ALL {
+                ui.selectable_value(&mut grid.mode, mode, mode.name());
+            }
+        });
+    //the combo boxes change on a click, so only drags and typing get merged
+    (grid != *map.grid_settings()).then(|| {
+        MapEdit::ChangeGrid(MapChangeGrid::new(grid).with_gesture(changed_gesture(&responses)))
+    })
+}
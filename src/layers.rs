@@ -0,0 +1,190 @@
+use crate::utils::changed_gesture;
+use egui_macroquad::egui;
+use macromapper_core::{hatching::HatchPattern, map::*};
+use new_egui_macroquad as egui_macroquad;
+
+//Layer currently being renamed, and the text typed so far
+pub type Renaming = Option<(usize, String)>;
+
+//The panel only reads the map: any change comes back as an edit so that it can be undone
+pub fn layer_panel(
+    ui: &mut egui::Ui,
+    map: &Map,
+    active_layer: &mut usize,
+    renaming: &mut Renaming,
+) -> Option<MapEdit> {
+    let mut edit = None;
+    let count = map.layer_count();
+    ui.heading("Layers");
+    ui.horizontal(|ui| {
+        if ui.button("Add").clicked() {
+            //new layers go above the active one, and become active
+            *active_layer += 1;
+            edit = Some(MapEdit::AddLayer(MapAddLayer::new(
+                *active_layer,
+                Layer::new(map.new_layer_name()),
+            )));
+        }
+        //tools always need a layer to draw on
+        if ui
+            .add_enabled(count > 1, egui::Button::new("Delete"))
+            .clicked()
+        {
+            edit = Some(MapEdit::RemoveLayer(MapRemoveLayer::new(*active_layer)));
+        }
+        if ui
+            .add_enabled(*active_layer + 1 < count, egui::Button::new("Up"))
+            .clicked()
+        {
+            edit = Some(MapEdit::MoveLayer(MapMoveLayer::new(
+                *active_layer,
+                *active_layer + 1,
+            )));
+            *active_layer += 1;
+        }
+        if ui
+            .add_enabled(*active_layer > 0, egui::Button::new("Down"))
+            .clicked()
+        {
+            edit = Some(MapEdit::MoveLayer(MapMoveLayer::new(
+                *active_layer,
+                *active_layer - 1,
+            )));
+            *active_layer -= 1;
+        }
+    });
+    ui.separator();
+    //Later layers are drawn on top, so they're listed first
+    for (i, layer) in map.layers_iter().enumerate().rev() {
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut layer.visible(), "")
+                .on_hover_text("Visible")
+                .clicked()
+            {
+                edit = Some(MapEdit::ToggleLayer(MapToggleLayer::new(
+                    i,
+                    LayerFlag::Visible,
+                )));
+            }
+            if ui
+                .checkbox(&mut layer.locked(), "")
+                .on_hover_text("Locked")
+                .clicked()
+            {
+                edit = Some(MapEdit::ToggleLayer(MapToggleLayer::new(
+                    i,
+                    LayerFlag::Locked,
+                )));
+            }
+            match renaming {
+                Some((index, name)) if *index == i => {
+                    let response = ui.text_edit_singleline(name);
+                    //focus the text box when it first appears
+                    if !response.has_focus() && !response.lost_focus() {
+                        response.request_focus();
+                    }
+                    if response.lost_focus() {
+                        //escape also takes the focus away, but it means keep the old name
+                        let cancelled = ui.input(|i| i.key_pressed(egui::Key::Escape));
+                        if !cancelled && !name.is_empty() && name != layer.name() {
+                            edit = Some(MapEdit::RenameLayer(MapRenameLayer::new(i, name.clone())));
+                        }
+                        *renaming = None;
+                    }
+                }
+                _ => {
+                    let response = ui
+                        .selectable_label(i == *active_layer, layer.name())
+                        .on_hover_text("Double-click to rename");
+                    if response.clicked() {
+                        *active_layer = i;
+                    }
+                    if response.double_clicked() {
+                        *renaming = Some((i, layer.name().to_string()));
+                    }
+                }
+            }
+        });
+    }
+    if let Some(layer) = map.layer(*active_layer) {
+        ui.separator();
+        let mut style = *layer.style();
+        let mut outline = style.outline_color.into();
+        let mut hatch = style.hatch_color.into();
+        let mut floor = style.floor_color.into();
+        let responses = egui::Grid::new("layer_style")
+            .show(ui, |ui| {
+                ui.label("Outline");
+                let outline_color = ui.color_edit_button_rgba_unmultiplied(&mut outline);
+                let outline_thickness =
+                    ui.add(egui::Slider::new(&mut style.outline_thickness, 0.5..=10.0));
+                ui.end_row();
+                ui.label("Hatching");
+                let hatch_color = ui.color_edit_button_rgba_unmultiplied(&mut hatch);
+                let hatch_thickness =
+                    ui.add(egui::Slider::new(&mut style.hatch_thickness, 0.5..=10.0));
+                ui.end_row();
+                ui.label("Floor");
+                let floor_color = ui.color_edit_button_rgba_unmultiplied(&mut floor);
+                ui.end_row();
+                [
+                    outline_color,
+                    outline_thickness,
+                    hatch_color,
+                    hatch_thickness,
+                    floor_color,
+                ]
+            })
+            .inner;
+        style.outline_color = outline.into();
+        style.hatch_color = hatch.into();
+        style.floor_color = floor.into();
+        if style != *layer.style() {
+            edit = Some(MapEdit::StyleLayer(
+                MapStyleLayer::new(*active_layer, style).with_gesture(changed_gesture(&responses)),
+            ));
+        }
+        let mut hatch = *layer.hatch();
+        let mut responses = vec![];
+        egui::ComboBox::from_label("Pattern")
+            .selected_text(hatch.pattern.name())
+            .show_ui(ui, |ui| {
+                for p in HatchPattern::ALL {
+                    ui.selectable_value(&mut hatch.pattern, p, p.name());
+                }
+            });
+        if hatch.pattern != HatchPattern::None {
+            responses.push(
+                ui.add(egui::Slider::new(&mut hatch.offset, 10.0..=150.0).text("Band width")),
+            );
+            responses
+                .push(ui.add(egui::Slider::new(&mut hatch.radius, 5.0..=50.0).text("Cell size")));
+        }
+        if matches!(
+            hatch.pattern,
+            HatchPattern::Lines | HatchPattern::CrossHatch
+        ) {
+            responses
+                .push(ui.add(egui::Slider::new(&mut hatch.hatch_count, 1..=20).text("Strokes")));
+            responses.push(
+                ui.add(
+                    egui::Slider::new(&mut hatch.min_length, 0.0..=30.0).text("Shortest stroke"),
+                ),
+            );
+        }
+        if hatch.pattern != HatchPattern::None {
+            ui.horizontal(|ui| {
+                responses.push(ui.add(egui::DragValue::new(&mut hatch.seed)));
+                ui.label("Seed");
+            });
+        }
+        //picking a pattern is a click, so it's never part of a gesture
+        if hatch != *layer.hatch() {
+            edit = Some(MapEdit::HatchLayer(
+                MapHatchLayer::new(*active_layer, hatch).with_gesture(changed_gesture(&responses)),
+            ));
+        }
+    }
+    edit
+}
@@ -1,19 +1,37 @@
-use camera::mouse;
 use egui_extras::{install_image_loaders, Size, StripBuilder};
-use egui_macroquad::egui::{self, emath::RectTransform, include_image, Image, Layout, Visuals};
+use egui_macroquad::egui::{self, emath::RectTransform, Layout, Visuals};
 use egui_macroquad::macroquad::{self, input, prelude::*};
 use ico::*;
+use macromapper_core::{
+    file::*,
+    grid::{GridMode, GridSettings},
+    hatching::Hatcher,
+    map::*,
+    sketch::{self, Sketch},
+    svg::export_rect,
+};
 use new_egui_macroquad as egui_macroquad;
+use std::path::PathBuf;
 use undo::Record;
 
 mod drawing;
 use drawing::*;
+mod export;
+use export::*;
+mod file;
+use file::*;
+mod grid_panel;
+use grid_panel::*;
+mod layers;
+use layers::*;
 mod utils;
 use utils::*;
 mod tools;
 use tools::*;
 
-const GRID_SIZE: f32 = 50.;
+//Clipping the grid takes a boolean op, so the grid is kept until the view or the visible
+//areas change. Layer revisions are unique, so they cover both.
+type GridKey = (Vec<u64>, Rect, GridSettings);
 
 //Window setup
 fn default_conf() -> Conf {
@@ -70,6 +88,7 @@ async fn main() {
     let mut mouse_grid_snapped: Vec2;
     let mut drag_started: Vec2 = vec2(0., 0.);
     let mut snap: f32 = 0.5;
+    let mut view_grid: Option<(GridKey, Option<Sketch>)> = None;
 
     //Camera globals
     // let res_scale: f32; //screen DPI scale (usually 1.0)
@@ -84,6 +103,11 @@ async fn main() {
     let drag_img = "file://assets/icons/d_drag.svg";
     let rect_img = "file://assets/icons/d_rect.svg";
     let poly_img = "file://assets/icons/d_pen.svg";
+    let select_img = "file://assets/icons/d_select.svg";
+    let vertex_img = "file://assets/icons/d_vertex.svg";
+    let circle_img = "file://assets/icons/d_circle.svg";
+    let corridor_img = "file://assets/icons/d_corridor.svg";
+    let brush_img = "file://assets/icons/d_brush.svg";
 
     let zoom_in_img = "file://assets/icons/z_zoom_in.svg";
     let zoom_out_img = "file://assets/icons/z_zoom_out.svg";
@@ -100,7 +124,22 @@ async fn main() {
     let mut active_map = Map::new();
     active_map.append_layer();
     let mut history = Record::<MapEdit>::new();
+    let mut hatcher = Hatcher::new();
+    let mut meshes = MeshCache::default();
     let mut active_layer: usize = 0;
+    let mut renaming: Renaming = None;
+    let mut file_path: Option<PathBuf> = None;
+    let mut file_error: Option<String> = None;
+    let mut last_autosave = get_time();
+    let mut export_settings = ExportSettings::default();
+    let mut export_dialog: Option<ExportFormat> = None;
+    let mut export_request: Option<(ExportFormat, PathBuf)> = None;
+    let mut recovery_prompt = recovery_path().is_some_and(|p| p.exists());
+    let mut quit_prompt = false;
+    let mut quit = false;
+
+    //Quitting is handled at the end of the main loop so the recovery file can be cleaned up
+    prevent_quit();
 
     loop {
         egui_macroquad::ui(|egui_ctx| {
@@ -111,8 +150,53 @@ async fn main() {
                 egui::menu::bar(ui, |ui| {
                     ui.menu_button("File", |ui| {
                         if ui.button("New").clicked() {
+                            active_map = Map::new();
+                            active_map.append_layer();
+                            history = Record::new();
+                            active_layer = 0;
+                            file_path = None;
+                            ui.close_menu();
+                        }
+                        if ui.button("Open...").clicked() {
+                            if let Some(path) = pick_open_path() {
+                                match load_map(&path) {
+                                    Ok((map, record)) => {
+                                        active_map = map;
+                                        history = record;
+                                        active_layer = 0;
+                                        file_path = Some(path);
+                                    }
+                                    Err(e) => file_error = Some(e.to_string()),
+                                }
+                            }
+                            ui.close_menu();
+                        }
+                        let save = ui.button("Save").clicked();
+                        let save_as = ui.button("Save As...").clicked();
+                        if save || save_as {
+                            //Save As always asks, Save only asks if the map has never been saved
+                            let path = if save_as { None } else { file_path.clone() };
+                            match save_or_ask(path, &active_map, &mut history) {
+                                Ok(Some(path)) => file_path = Some(path),
+                                Ok(None) => {}
+                                Err(e) => file_error = Some(e.to_string()),
+                            }
                             ui.close_menu();
                         }
+                        ui.separator();
+                        for (format, label) in [
+                            (ExportFormat::Png, "Export PNG..."),
+                            (ExportFormat::Svg, "Export SVG..."),
+                            (ExportFormat::Pdf, "Export PDF..."),
+                        ] {
+                            if ui.button(label).clicked() {
+                                export_dialog = Some(format);
+                                //start from what the view shows
+                                export_settings.grid = active_map.grid_settings().mode;
+                                ui.close_menu();
+                            }
+                        }
+                        ui.separator();
                         ui.menu_button("Preferences", |ui| {
                             if ui.button("Dark mode").clicked() {
                                 egui_ctx.set_visuals(Visuals::dark());
@@ -133,21 +217,184 @@ async fn main() {
                             camera.scale = *zoom_sizes
                                 .iter()
                                 .find(|&&e| e > camera.scale)
-                                .unwrap_or_else(|| &camera.scale);
+                                .unwrap_or(&camera.scale);
                         }
                         if ui.add(egui::ImageButton::new(zoom_out_img)).clicked() {
                             camera.scale = *zoom_sizes
                                 .iter()
                                 .rfind(|&&e| e < camera.scale)
-                                .unwrap_or_else(|| &camera.scale);
+                                .unwrap_or(&camera.scale);
                         }
                         ui.add(egui::Slider::new(&mut camera.scale, 0.1..=2.0).text("Zoom"));
                     });
                 });
             });
             egui::TopBottomPanel::bottom("bottom_panel").show(egui_ctx, |ui| {
-                ui.label("Test");
+                let name = file_path
+                    .as_ref()
+                    .and_then(|p| p.file_name())
+                    .map_or("Untitled".to_string(), |n| n.to_string_lossy().to_string());
+                ui.label(if history.is_saved() {
+                    name
+                } else {
+                    name + " *"
+                });
             });
+            if let Some(message) = &file_error {
+                let mut open = true;
+                egui::Window::new("Error")
+                    .collapsible(false)
+                    .resizable(false)
+                    .open(&mut open)
+                    .show(egui_ctx, |ui| {
+                        ui.label(message);
+                    });
+                if !open {
+                    file_error = None;
+                }
+            }
+            if let Some(format) = export_dialog {
+                let mut open = true;
+                let mut export_clicked = false;
+                egui::Window::new(format!("Export {}", format.name()))
+                    .collapsible(false)
+                    .resizable(false)
+                    .open(&mut open)
+                    .show(egui_ctx, |ui| {
+                        if format == ExportFormat::Png {
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut export_settings.pixels_per_square,
+                                    10.0..=400.0,
+                                )
+                                .text("Pixels per square"),
+                            );
+                        }
+                        if format == ExportFormat::Pdf {
+                            egui::ComboBox::from_label("Paper")
+                                .selected_text(export_settings.paper.name())
+                                .show_ui(ui, |ui| {
+                                    for paper in [Paper::Letter, Paper::A4] {
+                                        ui.selectable_value(
+                                            &mut export_settings.paper,
+                                            paper,
+                                            paper.name(),
+                                        );
+                                    }
+                                });
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut export_settings.inches_per_square,
+                                    0.25..=2.0,
+                                )
+                                .text("Inches per square"),
+                            );
+                            ui.add(
+                                egui::Slider::new(&mut export_settings.overlap, 0.0..=30.0)
+                                    .text("Page overlap (mm)"),
+                            );
+                        }
+                        egui::ComboBox::from_label("Grid")
+                            .selected_text(export_settings.grid.name())
+                            .show_ui(ui, |ui| {
+                                for mode in GridMode::ALL {
+                                    ui.selectable_value(
+                                        &mut export_settings.grid,
+                                        mode,
+                                        mode.name(),
+                                    );
+                                }
+                            });
+                        match export_rect(&active_map).map(mq_rect) {
+                            Some(rect) => {
+                                if format == ExportFormat::Png {
+                                    let scale = export_settings.pixels_per_square
+                                        / active_map.grid_settings().size;
+                                    ui.label(format!(
+                                        "{} x {} px",
+                                        (rect.w * scale).ceil(),
+                                        (rect.h * scale).ceil()
+                                    ));
+                                }
+                                if format == ExportFormat::Pdf {
+                                    let layout = page_layout(
+                                        rect,
+                                        active_map.grid_settings().size,
+                                        &export_settings,
+                                    );
+                                    ui.label(format!("{} x {} pages", layout.columns, layout.rows));
+                                }
+                                export_clicked = ui.button("Export").clicked();
+                            }
+                            None => {
+                                ui.label("The map is empty");
+                            }
+                        }
+                    });
+                if export_clicked {
+                    export_request = pick_export_path(format.name(), format.extension())
+                        .map(|path| (format, path));
+                }
+                if !open || export_clicked {
+                    export_dialog = None;
+                }
+            }
+            if recovery_prompt {
+                egui::Window::new("Recover map")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(egui_ctx, |ui| {
+                        ui.label("Macromapper didn't close properly last time.");
+                        ui.label("Restore the autosaved map?");
+                        ui.horizontal(|ui| {
+                            if ui.button("Restore").clicked() {
+                                match recovery_path().map(|p| load_map(&p)) {
+                                    Some(Ok((map, record))) => {
+                                        active_map = map;
+                                        history = record;
+                                        //the recovered map was never saved anywhere
+                                        history.set_saved(false);
+                                        file_path = None;
+                                    }
+                                    Some(Err(e)) => file_error = Some(e.to_string()),
+                                    None => {}
+                                }
+                                recovery_prompt = false;
+                            }
+                            if ui.button("Discard").clicked() {
+                                clear_recovery();
+                                recovery_prompt = false;
+                            }
+                        });
+                    });
+            }
+            if quit_prompt {
+                egui::Window::new("Unsaved changes")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(egui_ctx, |ui| {
+                        ui.label("Save changes to the map before closing?");
+                        ui.horizontal(|ui| {
+                            if ui.button("Save").clicked() {
+                                match save_or_ask(file_path.clone(), &active_map, &mut history) {
+                                    Ok(Some(_)) => quit = true,
+                                    //cancelling the dialog goes back to the prompt
+                                    Ok(None) => {}
+                                    Err(e) => {
+                                        file_error = Some(e.to_string());
+                                        quit_prompt = false;
+                                    }
+                                }
+                            }
+                            if ui.button("Discard").clicked() {
+                                quit = true;
+                            }
+                            if ui.button("Cancel").clicked() {
+                                quit_prompt = false;
+                            }
+                        });
+                    });
+            }
             egui::SidePanel::left("left_panel").show(egui_ctx, |ui| {
                 StripBuilder::new(ui)
                     .size(Size::exact(35.0))
@@ -175,6 +422,50 @@ async fn main() {
                                 tool = Box::new(PolyTool::new());
                                 selected_tool = 3;
                             }
+                            if ui
+                                .add(
+                                    egui::ImageButton::new(select_img).selected(selected_tool == 4),
+                                )
+                                .clicked()
+                            {
+                                tool = Box::new(SelectTool::new());
+                                selected_tool = 4;
+                            }
+                            if ui
+                                .add(
+                                    egui::ImageButton::new(vertex_img).selected(selected_tool == 5),
+                                )
+                                .clicked()
+                            {
+                                tool = Box::new(VertexTool::new());
+                                selected_tool = 5;
+                            }
+                            if ui
+                                .add(
+                                    egui::ImageButton::new(circle_img).selected(selected_tool == 6),
+                                )
+                                .clicked()
+                            {
+                                tool = Box::new(CircleTool::new());
+                                selected_tool = 6;
+                            }
+                            if ui
+                                .add(
+                                    egui::ImageButton::new(corridor_img)
+                                        .selected(selected_tool == 7),
+                                )
+                                .clicked()
+                            {
+                                tool = Box::new(CorridorTool::new());
+                                selected_tool = 7;
+                            }
+                            if ui
+                                .add(egui::ImageButton::new(brush_img).selected(selected_tool == 8))
+                                .clicked()
+                            {
+                                tool = Box::new(BrushTool::new());
+                                selected_tool = 8;
+                            }
                         });
 
                         strip.cell(|ui| {
@@ -194,16 +485,20 @@ async fn main() {
                                     ui.selectable_value(&mut snap, 0.2, "1/5");
                                     ui.selectable_value(&mut snap, 1.0 / 6.0, "1/6");
                                 });
+                            tool.options(ui);
+                            ui.separator();
+                            if let Some(edit) = grid_panel(ui, &active_map) {
+                                history.edit(&mut active_map, edit);
+                            }
                             //Fill with empty space to allow resizing
                             //ui.allocate_space(ui.available_size());
                         })
                     });
             });
             egui::SidePanel::right("right_panel").show(egui_ctx, |ui| {
-                ui.label("Test");
-                ui.group(|ui| {
-                    ui.label("Layer example");
-                });
+                if let Some(edit) = layer_panel(ui, &active_map, &mut active_layer, &mut renaming) {
+                    history.edit(&mut active_map, edit);
+                }
             });
             let available = egui_ctx.available_rect();
             let screen_to_relative = RectTransform::from_to(
@@ -226,28 +521,54 @@ async fn main() {
         // Process keys, mouse etc.
         mouse_new = mouse_position().into();
         mouse_pressed_new = is_mouse_button_down(MouseButton::Left);
-        mouse_pressed_r = is_mouse_button_down(MouseButton::Right);
+        mouse_pressed_r = is_mouse_button_pressed(MouseButton::Right);
 
         //This could be shorter but that sacrifices clarity
+        let mut pressed = false;
+        let mut released = false;
         if mouse_pressed_new && !mouse_in_egui {
             if !mouse_pressed_old && camera.screen_rect.contains(mouse_new) {
                 drag_started = mouse_new;
                 is_dragging = true;
+                pressed = true;
             }
         } else {
+            released = is_dragging;
             is_dragging = false;
         }
 
         // Handle coordinates
-        mouse_grid = (mouse_new - camera.screen_rect.point()) * vec2(1.0, -1.0) / camera.scale
-            + camera.grid_rect.point()
-            + vec2(0.0, camera.grid_rect.h);
-        mouse_grid_snapped = (mouse_grid / GRID_SIZE / snap).round() * GRID_SIZE * snap;
+        mouse_grid = camera.screen_to_world(mouse_new);
+        mouse_grid_snapped = mq_vec(
+            active_map
+                .grid_settings()
+                .grid()
+                .snap(geo_coord(mouse_grid), snap),
+        );
+
+        //Undoing a layer add or a delete can leave the active index past the end
+        active_layer = active_layer.min(active_map.layer_count().saturating_sub(1));
+        let layer_locked = active_map.layer(active_layer).is_some_and(|l| l.locked());
 
         //Update based on input
+        let op_type = if tool_type {
+            &PolyOpType::Union
+        } else {
+            &PolyOpType::Subtraction
+        };
+        if pressed {
+            tool.press(mouse_grid_snapped, mouse_grid, &active_map, active_layer);
+        }
         if is_dragging {
             tool.drag(mouse_new, mouse_old, &mut camera);
         }
+        if released {
+            if let Some(i) = tool.release(mouse_grid_snapped, &active_map, active_layer, op_type) {
+                if !layer_locked {
+                    history.edit(&mut active_map, i);
+                }
+            }
+        }
 
         if !mouse_pressed_new
             && mouse_pressed_old
@@ -255,22 +576,19 @@ async fn main() {
             && !mouse_in_egui
             && drag_started == mouse_old
         {
-            if let Some(i) = tool.left_click(
-                mouse_grid_snapped,
-                active_layer,
-                if tool_type {
-                    &PolyOpType::Union
-                } else {
-                    &PolyOpType::Subtraction
-                },
-            ) {
-                history.edit(&mut active_map, i);
+            if let Some(i) = tool.left_click(mouse_grid_snapped, &active_map, active_layer, op_type)
+            {
+                if !layer_locked {
+                    history.edit(&mut active_map, i);
+                }
             }
         }
 
         if mouse_pressed_r && camera.screen_rect.contains(mouse_new) && !mouse_in_egui {
-            if let Some(i) = tool.right_click(mouse_grid_snapped) {
-                history.edit(&mut active_map, i);
+            if let Some(i) = tool.right_click(mouse_grid, &active_map, active_layer) {
+                if !layer_locked {
+                    history.edit(&mut active_map, i);
+                }
             }
         }
 
@@ -288,48 +606,97 @@ async fn main() {
         mouse_old = mouse_new;
         mouse_pressed_old = mouse_pressed_new;
 
+        //Don't autosave while the prompt is up, or the recovery file would be overwritten
+        if !recovery_prompt && get_time() - last_autosave >= AUTOSAVE_INTERVAL {
+            last_autosave = get_time();
+            if history.is_saved() {
+                clear_recovery();
+            } else if let Err(e) = autosave(&active_map, &history) {
+                file_error = Some(format!("autosave failed: {e}"));
+            }
+        }
+
+        hatcher.update(&mut active_map);
+
+        //Offscreen rendering has to happen before the main camera is set up
+        if let Some((format, path)) = export_request.take() {
+            //don't wait on the hatcher, exports should never be missing hatching
+            active_map.regenerate_hatching();
+            let result = match format {
+                ExportFormat::Png => export_png(&active_map, &path, &export_settings),
+                ExportFormat::Svg => export_svg(&active_map, &path, &export_settings),
+                ExportFormat::Pdf => export_pdf(&active_map, &path, &export_settings),
+            };
+            if let Err(e) = result {
+                file_error = Some(e.to_string());
+            }
+        }
+
         //Set up camera
         set_camera(&camera.to_camera());
 
         //Grid
         clear_background(WHITE);
-        let mut grid = Sketch::new(1., LIGHTGRAY);
-        ((camera.grid_rect.left() / GRID_SIZE) as i32
-            ..=(camera.grid_rect.right() / GRID_SIZE) as i32)
-            .map(|i| {
-                grid.add(Line::new(
-                    i as f32 * GRID_SIZE,
-                    camera.grid_rect.top(),
-                    i as f32 * GRID_SIZE,
-                    camera.grid_rect.bottom(),
-                ));
-            })
-            .count();
-        ((camera.grid_rect.top() / GRID_SIZE) as i32
-            ..=(camera.grid_rect.bottom() / GRID_SIZE) as i32)
-            .map(|i| {
-                grid.add(Line::new(
-                    camera.grid_rect.left(),
-                    i as f32 * GRID_SIZE,
-                    camera.grid_rect.right(),
-                    i as f32 * GRID_SIZE,
-                ));
-            })
-            .count();
-        grid.draw();
-        for l in active_map.layers_iter() {
-            l.draw();
+        let grid_key = (
+            active_map.visible_layers().map(|l| l.revision()).collect(),
+            camera.grid_rect,
+            active_map.grid_settings().clone(),
+        );
+        if view_grid.as_ref().map(|(key, _)| key) != Some(&grid_key) {
+            let mode = active_map.grid_settings().mode;
+            let grid = active_map.grid(geo_rect(camera.grid_rect), mode);
+            view_grid = Some((grid_key, grid));
         }
+        let grid = view_grid.as_ref().and_then(|(_, grid)| grid.as_ref());
+        active_map.draw(grid, &mut meshes);
+        meshes.end_frame();
 
         //draw_circle(0., 0., 20., YELLOW);
 
         //Draw snapped cursor circle
         draw_circle(mouse_grid_snapped.x, mouse_grid_snapped.y, 3.0, RED);
 
-        tool.preview(mouse_grid_snapped, 1., RED).draw();
+        tool.preview(
+            mouse_grid_snapped,
+            mouse_grid,
+            &active_map,
+            active_layer,
+            1.,
+            sketch::RED,
+        )
+        .draw();
 
         egui_macroquad::draw();
         // Draw things after egui
+        set_default_camera();
+        if let Some(anchor) = tool.anchor() {
+            let size = (mouse_grid_snapped - anchor).abs();
+            let grid = active_map.grid_settings();
+            let text = format!(
+                "{} x {} ({})",
+                grid.measure(size.x),
+                grid.measure(size.y),
+                grid.measure(size.length())
+            );
+            draw_measurement(&text, mouse_new + vec2(16., -8.));
+        }
+
+        //Closing with unsaved changes asks first
+        if is_quit_requested() {
+            if history.is_saved() {
+                quit = true;
+            } else {
+                quit_prompt = true;
+            }
+        }
+        if quit {
+            //a clean exit, so there's nothing to recover next time. Unless the recovery
+            //prompt was never answered, then it's still there for next time
+            if !recovery_prompt {
+                clear_recovery();
+            }
+            break;
+        }
 
         next_frame().await;
     }
@@ -1,24 +1,70 @@
 use core::mem;
 
-use crate::{drawing::*, utils::*};
-use egui_macroquad::macroquad::prelude::*;
+use crate::utils::*;
+use egui_macroquad::{egui, macroquad::prelude::*};
+use geo::{
+    BooleanOps, BoundingRect, Contains, ConvexHull, EuclideanDistance, InteriorPoint, Intersects,
+    LinesIter, SimplifyVwPreserve, Translate,
+};
+use macromapper_core::{
+    map::*,
+    sketch::{self, Line, Sketch},
+};
 use new_egui_macroquad as egui_macroquad;
 
 pub trait Tool {
-    fn left_click(&mut self, pos: Vec2, layer: usize, op_type: &PolyOpType) -> Option<MapEdit>;
-    fn right_click(&mut self, pos: Vec2) -> Option<MapEdit>;
+    fn left_click(
+        &mut self,
+        pos: Vec2,
+        map: &Map,
+        layer: usize,
+        op_type: &PolyOpType,
+    ) -> Option<MapEdit>;
+    fn right_click(&mut self, cursor: Vec2, map: &Map, layer: usize) -> Option<MapEdit>;
     fn drag(&mut self, mouse_new: Vec2, mouse_old: Vec2, camera: &mut Cam) -> Option<MapEdit>;
-    fn preview(&mut self, pos: Vec2, thickness: f32, color: Color) -> Sketch;
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        cursor: Vec2,
+        map: &Map,
+        layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch;
+    //Where the shape being drawn was started from, so its size can be shown
+    fn anchor(&self) -> Option<Vec2> {
+        None
+    }
+    //The left button going down and back up on the canvas, for tools that do more than click.
+    //cursor is the unsnapped position, for picking things under the mouse
+    fn press(&mut self, _pos: Vec2, _cursor: Vec2, _map: &Map, _layer: usize) {}
+    fn release(
+        &mut self,
+        _pos: Vec2,
+        _map: &Map,
+        _layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        None
+    }
+    //Settings for the tool, shown in the left panel while it's selected
+    fn options(&mut self, _ui: &mut egui::Ui) {}
 }
 
 #[derive(PartialEq)]
 pub struct DragTool {}
 
 impl Tool for DragTool {
-    fn left_click(&mut self, _pos: Vec2, _layer: usize, _op_type: &PolyOpType) -> Option<MapEdit> {
+    fn left_click(
+        &mut self,
+        _pos: Vec2,
+        _map: &Map,
+        _layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
         None
     }
-    fn right_click(&mut self, _pos: Vec2) -> Option<MapEdit> {
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
         None
     }
     fn drag(&mut self, mouse_new: Vec2, mouse_old: Vec2, camera: &mut Cam) -> Option<MapEdit> {
@@ -27,7 +73,15 @@ impl Tool for DragTool {
         );
         None
     }
-    fn preview(&mut self, _pos: Vec2, thickness: f32, color: Color) -> Sketch {
+    fn preview(
+        &mut self,
+        _pos: Vec2,
+        _cursor: Vec2,
+        _map: &Map,
+        _layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
         Sketch::new(thickness, color)
     }
 }
@@ -44,17 +98,18 @@ impl RectTool {
 }
 
 impl Tool for RectTool {
-    fn left_click(&mut self, pos: Vec2, layer: usize, op_type: &PolyOpType) -> Option<MapEdit> {
+    fn left_click(
+        &mut self,
+        pos: Vec2,
+        _map: &Map,
+        layer: usize,
+        op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
         match self.point {
             Some(_) => {
                 let out = poly_rect(self.point.expect("there should be a first point"), pos);
                 self.point = None;
-                match op_type {
-                    PolyOpType::Union => Some(MapEdit::Union(MapUnion::new(layer, out))),
-                    PolyOpType::Subtraction => {
-                        Some(MapEdit::Subtraction(MapSubtraction::new(layer, out)))
-                    }
-                }
+                Some(shape_edit(layer, out, op_type))
             }
             None => {
                 self.point = Some(pos);
@@ -62,24 +117,31 @@ impl Tool for RectTool {
             }
         }
     }
-    fn right_click(&mut self, _pos: Vec2) -> Option<MapEdit> {
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
         self.point = None;
         None
     }
     fn drag(&mut self, _mouse_new: Vec2, _mouse_old: Vec2, _camera: &mut Cam) -> Option<MapEdit> {
         None
     }
-    fn preview(&mut self, pos: Vec2, thickness: f32, color: Color) -> Sketch {
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        _cursor: Vec2,
+        _map: &Map,
+        _layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
         let mut out = Sketch::new(thickness, color);
-        if self.point.is_some() {
-            let point = self.point.unwrap();
-            out.add(Line::new(point.x, point.y, pos.x, point.y));
-            out.add(Line::new(point.x, pos.y, pos.x, pos.y));
-            out.add(Line::new(point.x, point.y, point.x, pos.y));
-            out.add(Line::new(pos.x, point.y, pos.x, pos.y));
+        if let Some(point) = self.point {
+            add_rect(&mut out, point, pos);
         }
         out
     }
+    fn anchor(&self) -> Option<Vec2> {
+        self.point
+    }
 }
 
 pub struct PolyTool {
@@ -93,7 +155,13 @@ impl PolyTool {
 }
 
 impl Tool for PolyTool {
-    fn left_click(&mut self, pos: Vec2, layer: usize, op_type: &PolyOpType) -> Option<MapEdit> {
+    fn left_click(
+        &mut self,
+        pos: Vec2,
+        _map: &Map,
+        layer: usize,
+        op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
         if self.points.is_empty() {
             self.points.push(pos);
             return None;
@@ -101,7 +169,7 @@ impl Tool for PolyTool {
         if self.points.first().is_some_and(|p| *p == pos)
             || self.points.last().is_some_and(|p| *p == pos)
         {
-            let points = mem::replace(&mut self.points, vec![]);
+            let points = mem::take(&mut self.points);
             let coords = points
                 .into_iter()
                 .map(|v| geo::Coord {
@@ -113,12 +181,7 @@ impl Tool for PolyTool {
                 geo::LineString::from(coords),
                 vec![],
             )]);
-            return match op_type {
-                PolyOpType::Union => Some(MapEdit::Union(MapUnion::new(layer, polygon))),
-                PolyOpType::Subtraction => {
-                    Some(MapEdit::Subtraction(MapSubtraction::new(layer, polygon)))
-                }
-            };
+            return Some(shape_edit(layer, polygon, op_type));
         }
         if self.points.contains(&pos) {
             let index = self.points.iter().position(|p| *p == pos).unwrap();
@@ -129,7 +192,7 @@ impl Tool for PolyTool {
         None
     }
 
-    fn right_click(&mut self, _pos: Vec2) -> Option<MapEdit> {
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
         self.points.clear();
         None
     }
@@ -138,7 +201,15 @@ impl Tool for PolyTool {
         None
     }
 
-    fn preview(&mut self, pos: Vec2, thickness: f32, color: Color) -> Sketch {
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        _cursor: Vec2,
+        _map: &Map,
+        _layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
         let mut out = Sketch::new(thickness, color);
         self.points.push(pos);
         for pair in self.points.windows(2) {
@@ -147,4 +218,862 @@ impl Tool for PolyTool {
         self.points.pop();
         out
     }
+    //the segment being drawn starts at the last point
+    fn anchor(&self) -> Option<Vec2> {
+        self.points.last().copied()
+    }
+}
+
+fn shape_edit(layer: usize, shape: geo::MultiPolygon, op_type: &PolyOpType) -> MapEdit {
+    match op_type {
+        PolyOpType::Union => MapEdit::Union(MapUnion::new(layer, shape)),
+        PolyOpType::Subtraction => MapEdit::Subtraction(MapSubtraction::new(layer, shape)),
+    }
+}
+
+fn shift_down() -> bool {
+    is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift)
+}
+
+fn geo_point(v: Vec2) -> geo::Point {
+    geo::Point::new(v.x as f64, v.y as f64)
+}
+
+fn add_rect(sketch: &mut Sketch, a: Vec2, b: Vec2) {
+    sketch.add(Line::new(a.x, a.y, b.x, a.y));
+    sketch.add(Line::new(a.x, b.y, b.x, b.y));
+    sketch.add(Line::new(a.x, a.y, a.x, b.y));
+    sketch.add(Line::new(b.x, a.y, b.x, b.y));
+}
+
+//Picks whole rooms (the connected polygons of the active layer) by clicking or dragging a box,
+//and moves them by dragging. A selected room is remembered by a point inside it, since the
+//area gets rebuilt by every edit
+pub struct SelectTool {
+    layer: usize,
+    selected: Vec<geo::Point>,
+    start: Option<Vec2>,
+    moving: bool,
+}
+
+impl SelectTool {
+    pub fn new() -> Self {
+        SelectTool {
+            layer: 0,
+            selected: vec![],
+            start: None,
+            moving: false,
+        }
+    }
+    fn polygons<'a>(&self, map: &'a Map, layer: usize) -> Vec<&'a geo::Polygon> {
+        if layer != self.layer {
+            return vec![];
+        }
+        map.layer(layer).map_or(vec![], |l| {
+            l.area()
+                .iter()
+                .filter(|p| self.selected.iter().any(|point| p.contains(point)))
+                .collect()
+        })
+    }
+    fn offset(&self, pos: Vec2) -> Vec2 {
+        match self.start {
+            Some(start) if self.moving => pos - start,
+            _ => Vec2::ZERO,
+        }
+    }
+}
+
+impl Tool for SelectTool {
+    fn left_click(
+        &mut self,
+        _pos: Vec2,
+        _map: &Map,
+        _layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        None
+    }
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
+        self.selected.clear();
+        self.start = None;
+        None
+    }
+    fn drag(&mut self, _mouse_new: Vec2, _mouse_old: Vec2, _camera: &mut Cam) -> Option<MapEdit> {
+        None
+    }
+    //Pressing on a room selects it (unless it's already part of the selection) and starts
+    //moving, pressing on empty space starts a selection box
+    fn press(&mut self, pos: Vec2, cursor: Vec2, map: &Map, layer: usize) {
+        if layer != self.layer {
+            self.selected.clear();
+            self.layer = layer;
+        }
+        let Some(target_layer) = map.layer(layer) else {
+            return;
+        };
+        let cursor = geo_point(cursor);
+        self.start = Some(pos);
+        self.moving = false;
+        let Some(hit) = target_layer.area().iter().find(|p| p.contains(&cursor)) else {
+            self.selected.clear();
+            return;
+        };
+        if !self.polygons(map, layer).contains(&hit) {
+            self.selected = hit.interior_point().into_iter().collect();
+        }
+        self.moving = !target_layer.locked();
+    }
+    fn release(
+        &mut self,
+        pos: Vec2,
+        map: &Map,
+        layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        let start = self.start.take()?;
+        if self.moving {
+            let offset = pos - start;
+            let polygons = self.polygons(map, layer);
+            if offset == Vec2::ZERO || polygons.is_empty() {
+                return None;
+            }
+            let polygons = geo::MultiPolygon(polygons.into_iter().cloned().collect());
+            let offset = geo::coord! {x: offset.x as f64, y: offset.y as f64};
+            for point in &mut self.selected {
+                *point = point.translate(offset.x, offset.y);
+            }
+            return Some(MapEdit::Move(MapMove::new(layer, polygons, offset)));
+        }
+        //everything that fits in the box
+        let (min, max) = (start.min(pos), start.max(pos));
+        if let Some(target_layer) = map.layer(layer) {
+            self.selected = target_layer
+                .area()
+                .iter()
+                .filter(|p| {
+                    p.bounding_rect().is_some_and(|r| {
+                        r.min().x >= min.x as f64
+                            && r.min().y >= min.y as f64
+                            && r.max().x <= max.x as f64
+                            && r.max().y <= max.y as f64
+                    })
+                })
+                .filter_map(|p| p.interior_point())
+                .collect();
+        }
+        None
+    }
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        _cursor: Vec2,
+        map: &Map,
+        layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
+        let mut out = Sketch::new(thickness, color);
+        let offset = self.offset(pos);
+        for polygon in self.polygons(map, layer) {
+            let moved = polygon.translate(offset.x as f64, offset.y as f64);
+            for line in moved.lines_iter() {
+                out.add(Line::from_geo(line));
+            }
+        }
+        if let Some(start) = self.start.filter(|_| !self.moving) {
+            add_rect(&mut out, start, pos);
+        }
+        out
+    }
+    //shows how far the rooms have been moved, or the size of the box
+    fn anchor(&self) -> Option<Vec2> {
+        self.start
+    }
+}
+
+//The rings of a polygon without their repeated closing point, exterior first
+fn rings(polygon: &geo::Polygon) -> Vec<Vec<geo::Coord>> {
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| ring.0[..ring.0.len().saturating_sub(1)].to_vec())
+        .collect()
+}
+
+fn from_rings(mut rings: Vec<Vec<geo::Coord>>) -> geo::Polygon {
+    let exterior = rings.remove(0);
+    geo::Polygon::new(
+        exterior.into(),
+        rings.into_iter().map(geo::LineString::from).collect(),
+    )
+}
+
+//Boolean ops can't cope with edges crossing each other, so those reshapes are refused
+fn is_simple(rings: &[Vec<geo::Coord>]) -> bool {
+    let edges: Vec<geo::Line> = rings
+        .iter()
+        .flat_map(|ring| {
+            (0..ring.len()).map(|i| geo::Line::new(ring[i], ring[(i + 1) % ring.len()]))
+        })
+        .collect();
+    rings.iter().all(|ring| ring.len() >= 3)
+        && edges.iter().enumerate().all(|(i, a)| {
+            edges[i + 1..].iter().all(|b| {
+                let neighbours = a.end == b.start || b.end == a.start;
+                neighbours || !a.intersects(b)
+            })
+        })
+}
+
+//Handles are picked within a fraction of a grid cell, so they stay usable at any grid size
+fn pick_radius(map: &Map) -> f64 {
+    map.grid_settings().size as f64 * 0.25
+}
+
+//(polygon, ring, vertex) of the closest vertex within reach
+fn nearest_vertex(
+    area: &geo::MultiPolygon,
+    cursor: geo::Coord,
+    reach: f64,
+) -> Option<(usize, usize, usize)> {
+    let mut best = None;
+    let mut best_distance = reach;
+    for (p, polygon) in area.iter().enumerate() {
+        for (r, ring) in rings(polygon).iter().enumerate() {
+            for (v, vertex) in ring.iter().enumerate() {
+                let distance =
+                    geo::Point::from(*vertex).euclidean_distance(&geo::Point::from(cursor));
+                if distance <= best_distance {
+                    best = Some((p, r, v));
+                    best_distance = distance;
+                }
+            }
+        }
+    }
+    best
+}
+
+//Same as nearest_vertex, but the edge after the returned vertex
+fn nearest_edge(
+    area: &geo::MultiPolygon,
+    cursor: geo::Coord,
+    reach: f64,
+) -> Option<(usize, usize, usize)> {
+    let mut best = None;
+    let mut best_distance = reach;
+    for (p, polygon) in area.iter().enumerate() {
+        for (r, ring) in rings(polygon).iter().enumerate() {
+            for v in 0..ring.len() {
+                let edge = geo::Line::new(ring[v], ring[(v + 1) % ring.len()]);
+                let distance = geo::Point::from(cursor).euclidean_distance(&edge);
+                if distance <= best_distance {
+                    best = Some((p, r, v));
+                    best_distance = distance;
+                }
+            }
+        }
+    }
+    best
+}
+
+struct VertexDrag {
+    polygon: geo::Polygon,
+    rings: Vec<Vec<geo::Coord>>,
+    ring: usize,
+    vertex: usize,
+    inserted: bool,
+}
+
+impl VertexDrag {
+    fn moved_to(&self, pos: Vec2) -> Vec<Vec<geo::Coord>> {
+        let mut rings = self.rings.clone();
+        rings[self.ring][self.vertex] = geo::coord! {x: pos.x as f64, y: pos.y as f64};
+        rings
+    }
+}
+
+//Edits the corners of the active layer. Dragging a handle moves a vertex, pressing on an edge
+//adds one there (and drags it), right clicking a handle deletes it
+pub struct VertexTool {
+    drag: Option<VertexDrag>,
+}
+
+impl VertexTool {
+    pub fn new() -> Self {
+        VertexTool { drag: None }
+    }
+}
+
+impl Tool for VertexTool {
+    fn left_click(
+        &mut self,
+        _pos: Vec2,
+        _map: &Map,
+        _layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        None
+    }
+    fn right_click(&mut self, cursor: Vec2, map: &Map, layer: usize) -> Option<MapEdit> {
+        self.drag = None;
+        let area = map.layer(layer)?.area();
+        let cursor = geo::coord! {x: cursor.x as f64, y: cursor.y as f64};
+        let (p, r, v) = nearest_vertex(area, cursor, pick_radius(map))?;
+        let mut rings = rings(&area.0[p]);
+        //a hole can't get any smaller than a triangle, so it closes up instead
+        if r > 0 && rings[r].len() <= 3 {
+            rings.remove(r);
+        } else {
+            rings[r].remove(v);
+        }
+        is_simple(&rings)
+            .then(|| MapEdit::Reshape(MapReshape::new(layer, area.0[p].clone(), from_rings(rings))))
+    }
+    fn drag(&mut self, _mouse_new: Vec2, _mouse_old: Vec2, _camera: &mut Cam) -> Option<MapEdit> {
+        None
+    }
+    fn press(&mut self, pos: Vec2, cursor: Vec2, map: &Map, layer: usize) {
+        self.drag = None;
+        let Some(target_layer) = map.layer(layer) else {
+            return;
+        };
+        let area = target_layer.area();
+        let cursor = geo::coord! {x: cursor.x as f64, y: cursor.y as f64};
+        let reach = pick_radius(map);
+        let (p, ring, vertex, inserted) =
+            if let Some((p, r, v)) = nearest_vertex(area, cursor, reach) {
+                (p, r, v, false)
+            } else if let Some((p, r, v)) = nearest_edge(area, cursor, reach) {
+                (p, r, v + 1, true)
+            } else {
+                return;
+            };
+        let polygon = area.0[p].clone();
+        let mut rings = rings(&polygon);
+        if inserted {
+            rings[ring].insert(vertex, geo::coord! {x: pos.x as f64, y: pos.y as f64});
+        }
+        self.drag = Some(VertexDrag {
+            polygon,
+            rings,
+            ring,
+            vertex,
+            inserted,
+        });
+    }
+    fn release(
+        &mut self,
+        pos: Vec2,
+        _map: &Map,
+        layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        let drag = self.drag.take()?;
+        let rings = drag.moved_to(pos);
+        if !drag.inserted && rings == drag.rings {
+            return None;
+        }
+        is_simple(&rings)
+            .then(|| MapEdit::Reshape(MapReshape::new(layer, drag.polygon, from_rings(rings))))
+    }
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        _cursor: Vec2,
+        map: &Map,
+        layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
+        let mut out = Sketch::new(thickness, color);
+        let size = pick_radius(map) as f32 / 2.;
+        let handle = |out: &mut Sketch, c: geo::Coord| {
+            let c = vec2(c.x as f32, c.y as f32);
+            add_rect(out, c - size / 2., c + size / 2.);
+        };
+        if let Some(target_layer) = map.layer(layer) {
+            for polygon in target_layer.area() {
+                for ring in rings(polygon) {
+                    for vertex in ring {
+                        handle(&mut out, vertex);
+                    }
+                }
+            }
+        }
+        //the reshaped outline follows the mouse
+        if let Some(drag) = &self.drag {
+            let moved = from_rings(drag.moved_to(pos));
+            for line in moved.lines_iter() {
+                out.add(Line::from_geo(line));
+            }
+            handle(&mut out, geo::coord! {x: pos.x as f64, y: pos.y as f64});
+        }
+        out
+    }
+    //shows how far the vertex has been dragged
+    fn anchor(&self) -> Option<Vec2> {
+        let drag = self.drag.as_ref()?;
+        let start = drag.rings[drag.ring][drag.vertex];
+        Some(vec2(start.x as f32, start.y as f32))
+    }
+}
+
+//Clicks the centre, then a point on the edge. Holding shift makes an ellipse that fits the
+//box around the centre instead, so the two radii can differ
+pub struct CircleTool {
+    center: Option<Vec2>,
+    segments: usize,
+}
+
+impl CircleTool {
+    pub fn new() -> Self {
+        CircleTool {
+            center: None,
+            segments: 32,
+        }
+    }
+    fn radii(center: Vec2, pos: Vec2) -> Vec2 {
+        if shift_down() {
+            (pos - center).abs()
+        } else {
+            Vec2::splat(pos.distance(center))
+        }
+    }
+}
+
+impl Tool for CircleTool {
+    fn left_click(
+        &mut self,
+        pos: Vec2,
+        _map: &Map,
+        layer: usize,
+        op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        let Some(center) = self.center else {
+            self.center = Some(pos);
+            return None;
+        };
+        let radii = Self::radii(center, pos);
+        if radii.min_element() <= 0. {
+            return None;
+        }
+        self.center = None;
+        Some(shape_edit(
+            layer,
+            poly_ellipse(center, radii, self.segments),
+            op_type,
+        ))
+    }
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
+        self.center = None;
+        None
+    }
+    fn drag(&mut self, _mouse_new: Vec2, _mouse_old: Vec2, _camera: &mut Cam) -> Option<MapEdit> {
+        None
+    }
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        _cursor: Vec2,
+        _map: &Map,
+        _layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
+        let mut out = Sketch::new(thickness, color);
+        if let Some(center) = self.center {
+            let shape = poly_ellipse(center, Self::radii(center, pos), self.segments);
+            for line in shape.lines_iter() {
+                out.add(Line::from_geo(line));
+            }
+            out.add(Line::new(center.x, center.y, pos.x, pos.y));
+        }
+        out
+    }
+    fn anchor(&self) -> Option<Vec2> {
+        self.center
+    }
+    fn options(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.segments, 6..=128).text("Segments"));
+        ui.label("Hold shift for an ellipse");
+    }
+}
+
+//Like the polygon tool, but the clicked path is widened into a corridor. Clicking the last
+//point again finishes it
+pub struct CorridorTool {
+    points: Vec<Vec2>,
+    //in grid cells
+    width: f32,
+    join: Join,
+}
+
+impl CorridorTool {
+    pub fn new() -> Self {
+        CorridorTool {
+            points: vec![],
+            width: 1.,
+            join: Join::Square,
+        }
+    }
+    fn shape(&self, points: &[Vec2], map: &Map) -> geo::MultiPolygon {
+        poly_corridor(points, self.width * map.grid_settings().size, self.join)
+    }
+}
+
+impl Tool for CorridorTool {
+    fn left_click(
+        &mut self,
+        pos: Vec2,
+        map: &Map,
+        layer: usize,
+        op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        if self.points.last() != Some(&pos) {
+            self.points.push(pos);
+            return None;
+        }
+        let points = mem::take(&mut self.points);
+        (points.len() > 1).then(|| shape_edit(layer, self.shape(&points, map), op_type))
+    }
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
+        self.points.clear();
+        None
+    }
+    fn drag(&mut self, _mouse_new: Vec2, _mouse_old: Vec2, _camera: &mut Cam) -> Option<MapEdit> {
+        None
+    }
+    fn preview(
+        &mut self,
+        pos: Vec2,
+        _cursor: Vec2,
+        map: &Map,
+        _layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
+        let mut out = Sketch::new(thickness, color);
+        if self.points.is_empty() {
+            return out;
+        }
+        let mut points = self.points.clone();
+        points.push(pos);
+        for pair in points.windows(2) {
+            out.add(Line::new(pair[0].x, pair[0].y, pair[1].x, pair[1].y));
+        }
+        for line in self.shape(&points, map).lines_iter() {
+            out.add(Line::from_geo(line));
+        }
+        out
+    }
+    fn anchor(&self) -> Option<Vec2> {
+        self.points.last().copied()
+    }
+    fn options(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.width, 0.5..=4.0)
+                .step_by(0.5)
+                .text("Width (cells)"),
+        );
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.join, Join::Square, "Square");
+            ui.selectable_value(&mut self.join, Join::Round, "Round");
+        });
+        ui.label("Click the last point again to finish");
+    }
+}
+
+//Paints with a round brush while the mouse is held down. The stroke is built up as the mouse
+//moves and added (or erased) as a single edit when it's let go
+pub struct BrushTool {
+    //in grid cells
+    size: f32,
+    radius: f32,
+    last: Option<Vec2>,
+    stroke: geo::MultiPolygon,
+}
+
+impl BrushTool {
+    pub fn new() -> Self {
+        BrushTool {
+            size: 1.,
+            radius: 0.,
+            last: None,
+            stroke: geo::MultiPolygon::new(vec![]),
+        }
+    }
+    fn paint(&mut self, pos: Vec2) {
+        //tiny steps would only add vertices
+        let points = match self.last {
+            Some(last) if last.distance(pos) < self.radius / 4. => return,
+            Some(last) => vec![last, pos],
+            None => vec![pos],
+        };
+        //one convex shape per step, overlapping rectangles and circles trip up geo's boolean ops
+        let circles = points
+            .iter()
+            .flat_map(|p| poly_ellipse(*p, Vec2::splat(self.radius), 32))
+            .collect::<geo::MultiPolygon>();
+        let piece = geo::MultiPolygon::new(vec![circles.convex_hull()]);
+        self.stroke = round_coords(&self.stroke.union(&round_coords(&piece)));
+        self.last = Some(pos);
+    }
+    //The stroke is made of a lot of little circles. Dropping the points that add less area
+    //than a sliver of the brush (a twentieth of it wide, half of it long) keeps the outline
+    //close with far fewer of them.
+    //The topology preserving version keeps each polygon's rings from crossing, but not the
+    //polygons from crossing each other, and rounding afterwards could too. Then the stroke is
+    //kept as it was painted.
+    fn simplify(&self, stroke: geo::MultiPolygon) -> geo::MultiPolygon {
+        let tolerance = self.radius as f64 / 20. * self.radius as f64 / 2.;
+        let simplified = round_coords(&stroke.simplify_vw_preserve(&tolerance));
+        let all_rings: Vec<_> = simplified.iter().flat_map(rings).collect();
+        if is_simple(&all_rings) {
+            simplified
+        } else {
+            stroke
+        }
+    }
+}
+
+impl Tool for BrushTool {
+    fn left_click(
+        &mut self,
+        _pos: Vec2,
+        _map: &Map,
+        _layer: usize,
+        _op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        None
+    }
+    fn right_click(&mut self, _cursor: Vec2, _map: &Map, _layer: usize) -> Option<MapEdit> {
+        None
+    }
+    //freehand, so the brush follows the mouse instead of the snapped point
+    fn press(&mut self, _pos: Vec2, cursor: Vec2, map: &Map, _layer: usize) {
+        self.radius = self.size * map.grid_settings().size / 2.;
+        self.last = None;
+        self.stroke = geo::MultiPolygon::new(vec![]);
+        self.paint(cursor);
+    }
+    fn drag(&mut self, mouse_new: Vec2, _mouse_old: Vec2, camera: &mut Cam) -> Option<MapEdit> {
+        if self.last.is_some() {
+            self.paint(camera.screen_to_world(mouse_new));
+        }
+        None
+    }
+    fn release(
+        &mut self,
+        _pos: Vec2,
+        _map: &Map,
+        layer: usize,
+        op_type: &PolyOpType,
+    ) -> Option<MapEdit> {
+        self.last = None;
+        let stroke = mem::replace(&mut self.stroke, geo::MultiPolygon::new(vec![]));
+        let stroke = self.simplify(stroke);
+        (!stroke.0.is_empty()).then(|| shape_edit(layer, stroke, op_type))
+    }
+    fn preview(
+        &mut self,
+        _pos: Vec2,
+        cursor: Vec2,
+        map: &Map,
+        _layer: usize,
+        thickness: f32,
+        color: sketch::Color,
+    ) -> Sketch {
+        let mut out = Sketch::new(thickness, color);
+        for line in self.stroke.lines_iter() {
+            out.add(Line::from_geo(line));
+        }
+        //the brush itself, at the real mouse position
+        let radius = self.size * map.grid_settings().size / 2.;
+        for line in poly_ellipse(cursor, Vec2::splat(radius), 32).lines_iter() {
+            out.add(Line::from_geo(line));
+        }
+        out
+    }
+    fn options(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.size, 0.25..=4.0)
+                .step_by(0.25)
+                .text("Brush size (cells)"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::{coord, polygon, Area, CoordsIter};
+    use undo::Record;
+
+    #[test]
+    fn reshaped_rings() {
+        let square = polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 10.),
+            (x: 10., y: 10.),
+            (x: 10., y: 0.)
+        ];
+        let mut rings = rings(&square);
+        assert_eq!(rings[0].len(), 4);
+        assert_eq!(from_rings(rings.clone()), square);
+        assert!(is_simple(&rings));
+        //pulling a corner across the far side makes a bowtie
+        rings[0][1] = coord! {x: 15., y: 5.};
+        assert!(!is_simple(&rings));
+        rings[0].remove(1);
+        assert!(is_simple(&rings));
+        rings[0].remove(1);
+        assert!(!is_simple(&rings));
+    }
+
+    #[test]
+    fn deleting_from_a_triangle_hole_fills_it() {
+        let mut map = Map::new();
+        map.append_layer();
+        let mut history = Record::<MapEdit>::new();
+        let room = geo::Polygon::new(
+            geo::LineString::from(vec![(0., 0.), (0., 200.), (200., 200.), (200., 0.)]),
+            vec![geo::LineString::from(vec![
+                (50., 50.),
+                (150., 50.),
+                (100., 150.),
+            ])],
+        );
+        history.edit(
+            &mut map,
+            MapEdit::Union(MapUnion::new(0, geo::MultiPolygon::new(vec![room]))),
+        );
+        assert_eq!(map.layer(0).unwrap().area().0[0].interiors().len(), 1);
+        let mut tool = VertexTool::new();
+        let edit = tool.right_click(vec2(100., 150.), &map, 0).unwrap();
+        history.edit(&mut map, edit);
+        let area = map.layer(0).unwrap().area();
+        assert!(area.0[0].interiors().is_empty());
+        assert_eq!(area.unsigned_area(), 40000.);
+    }
+
+    #[test]
+    fn ellipse_shape() {
+        let circle = poly_ellipse(vec2(10., 10.), vec2(50., 50.), 64);
+        assert_eq!(circle.0[0].exterior().0.len(), 65);
+        let circle_area = std::f64::consts::PI * 50. * 50.;
+        assert!((circle.unsigned_area() - circle_area).abs() / circle_area < 0.01);
+        let ellipse = poly_ellipse(vec2(0., 0.), vec2(100., 25.), 64);
+        let rect = ellipse.bounding_rect().unwrap();
+        assert_eq!((rect.width(), rect.height()), (200., 50.));
+    }
+
+    #[test]
+    fn corridor_shape() {
+        let points = [vec2(0., 0.), vec2(100., 0.), vec2(100., 100.)];
+        //an L of two 100 long arms, 10 wide, with square ends and corner
+        let square = poly_corridor(&points, 10., Join::Square);
+        assert_eq!(square.0.len(), 1);
+        assert!((square.unsigned_area() - (110. + 100.) * 10.).abs() < 1e-6);
+        let rect = square.bounding_rect().unwrap();
+        assert_eq!(
+            (rect.min(), rect.max()),
+            (coord! {x: -5., y: -5.}, coord! {x: 105., y: 105.})
+        );
+        //round ends and corner are a bit smaller than square ones
+        let round = poly_corridor(&points, 10., Join::Round);
+        assert_eq!(round.0.len(), 1);
+        assert!(round.unsigned_area() < square.unsigned_area());
+        assert!(round.unsigned_area() > 200. * 10.);
+        //a sharp turn is still one piece
+        let sharp = poly_corridor(
+            &[vec2(0., 0.), vec2(100., 0.), vec2(0., 10.)],
+            10.,
+            Join::Square,
+        );
+        assert_eq!(sharp.0.len(), 1);
+    }
+
+    #[test]
+    fn overlapping_corridor() {
+        //used to panic inside geo
+        let points = [
+            vec2(150., 75.),
+            vec2(75., 25.),
+            vec2(150., -75.),
+            vec2(250., 75.),
+            vec2(350., 0.),
+            vec2(275., -50.),
+            vec2(350., 75.),
+            vec2(450., 0.),
+        ];
+        for join in [Join::Square, Join::Round] {
+            assert!(!poly_corridor(&points, 50., join).0.is_empty());
+        }
+    }
+
+    #[test]
+    fn simplified_strokes_dont_overlap() {
+        //a notch in one part of the stroke with another part sticking into it. Dropping the
+        //notch's corner would straighten the edge right through the other part
+        let notched = polygon![
+            (x: 0., y: 0.),
+            (x: 0., y: 100.),
+            (x: 50., y: 90.),
+            (x: 100., y: 100.),
+            (x: 100., y: 0.)
+        ];
+        let spike = polygon![(x: 45., y: 95.), (x: 50., y: 110.), (x: 55., y: 95.)];
+        let stroke = geo::MultiPolygon::new(vec![notched, spike]);
+        let mut brush = BrushTool::new();
+        brush.radius = 200.;
+        let simplified = brush.simplify(stroke.clone());
+        assert_eq!(simplified, stroke);
+    }
+
+    #[test]
+    fn brush_stroke_is_one_edit() {
+        let wavy = (1..=400).map(|i| {
+            let t = i as f32 / 10.;
+            vec2(
+                3. + t * 37.,
+                4. + (t / 3.).sin() * 130. + (t * 7.).cos() * 9.,
+            )
+        });
+        //out and back again with only a thin slot between the two ways, which
+        //simplifying mustn't close up
+        let back = (0..=60)
+            .map(|i| vec2(i as f32 * 5., 0.))
+            .chain((0..=60).rev().map(|i| vec2(i as f32 * 5., 55.)));
+        let paths: [Vec<Vec2>; 2] = [wavy.collect(), back.collect()];
+        for path in paths {
+            let mut map = Map::new();
+            map.append_layer();
+            let mut history = Record::<MapEdit>::new();
+            let mut brush = BrushTool::new();
+            brush.press(vec2(0., 0.), vec2(3., 4.), &map, 0);
+            for pos in path {
+                brush.paint(pos);
+            }
+            assert_eq!(brush.stroke.0.len(), 1);
+            let painted = brush.stroke.clone();
+            let edit = brush
+                .release(vec2(0., 0.), &map, 0, &PolyOpType::Union)
+                .unwrap();
+            assert!(matches!(edit, MapEdit::Union(_)));
+            assert!(brush.stroke.0.is_empty());
+            history.edit(&mut map, edit);
+
+            //simplifying drops most of the points but keeps the shape
+            let area = map.layer(0).unwrap().area();
+            let points = |area: &geo::MultiPolygon| area.exterior_coords_iter().count();
+            assert!(points(area) * 2 < points(&painted));
+            let change = (area.unsigned_area() - painted.unsigned_area()).abs();
+            assert!(change / painted.unsigned_area() < 0.03);
+            assert!(area.iter().all(|p| is_simple(&rings(p))));
+            let slot = geo::Point::new(150., 27.5);
+            assert_eq!(area.contains(&slot), painted.contains(&slot));
+            history.undo(&mut map);
+            assert!(map.layer(0).unwrap().area().0.is_empty());
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use egui_macroquad::{egui, macroquad::prelude::*};
+use geo::{BooleanOps, MapCoords};
 use new_egui_macroquad as egui_macroquad;
 use std::collections::HashMap;
 
@@ -41,6 +42,109 @@ pub fn poly_rect(p1: Vec2, p2: Vec2) -> geo::MultiPolygon {
     )])
 }
 
+//An ellipse with the given radii (a circle if they're the same) as a polygon with segments sides
+pub fn poly_ellipse(center: Vec2, radii: Vec2, segments: usize) -> geo::MultiPolygon {
+    let points = (0..segments)
+        .map(|i| {
+            let angle = i as f64 / segments as f64 * std::f64::consts::TAU;
+            (
+                center.x as f64 + radii.x as f64 * angle.cos(),
+                center.y as f64 + radii.y as f64 * angle.sin(),
+            )
+        })
+        .collect::<Vec<_>>();
+    geo::MultiPolygon::new(vec![geo::Polygon::new(
+        geo::LineString::from(points),
+        vec![],
+    )])
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Join {
+    Square,
+    Round,
+}
+
+//The path through points widened to width. Each segment is a rectangle, the gaps at the
+//corners are filled with a mitre (a bevel on very sharp turns) or a circle
+pub fn poly_corridor(points: &[Vec2], width: f32, join: Join) -> geo::MultiPolygon {
+    let mut points = points.to_vec();
+    points.dedup();
+    let half = width / 2.;
+    let polygon = |corners: &[Vec2]| {
+        geo::Polygon::new(
+            geo::LineString::from(
+                corners
+                    .iter()
+                    .map(|c| (c.x as f64, c.y as f64))
+                    .collect::<Vec<_>>(),
+            ),
+            vec![],
+        )
+    };
+    let mut pieces = vec![];
+    for (i, pair) in points.windows(2).enumerate() {
+        let dir = (pair[1] - pair[0]).normalize();
+        let normal = dir.perp() * half;
+        let (mut a, mut b) = (pair[0], pair[1]);
+        //square ends stick out half the width, like the corners do
+        if join == Join::Square {
+            if i == 0 {
+                a -= dir * half;
+            }
+            if i + 2 == points.len() {
+                b += dir * half;
+            }
+        }
+        pieces.push(polygon(&[a + normal, b + normal, b - normal, a - normal]));
+    }
+    for (i, &point) in points.iter().enumerate() {
+        let (before, after) = (points.get(i.wrapping_sub(1)), points.get(i + 1));
+        match (join, before, after) {
+            (Join::Round, _, _) => pieces.extend(poly_ellipse(point, Vec2::splat(half), 32)),
+            (Join::Square, Some(&before), Some(&after)) => {
+                let (d1, d2) = ((point - before).normalize(), (after - point).normalize());
+                let turn = d1.perp_dot(d2);
+                if turn.abs() < 1e-6 {
+                    //doubling back gets the same end as the last point, going straight needs nothing
+                    if d1.dot(d2) < 0. {
+                        let (end, normal) = (point + d1 * half, d1.perp() * half);
+                        pieces.push(polygon(&[
+                            point + normal,
+                            end + normal,
+                            end - normal,
+                            point - normal,
+                        ]));
+                    }
+                    continue;
+                }
+                //the corner is on the outside of the turn
+                let (n1, n2) = (d1.perp() * -turn.signum(), d2.perp() * -turn.signum());
+                let (o1, o2) = (point + n1 * half, point + n2 * half);
+                let bisector = (n1 + n2).normalize();
+                let mitre = half / bisector.dot(n1);
+                if mitre <= width * 2. {
+                    pieces.push(polygon(&[point, o1, point + bisector * mitre, o2]));
+                } else {
+                    pieces.push(polygon(&[point, o1, o2]));
+                }
+            }
+            _ => {}
+        }
+    }
+    pieces
+        .into_iter()
+        .fold(geo::MultiPolygon::new(vec![]), |out, piece| {
+            round_coords(&out.union(&geo::MultiPolygon::new(vec![round_coords(&piece)])))
+        })
+}
+
+//geo's boolean ops can panic on points that are almost but not quite the same, like where
+//pieces of a path overlap. Snapping to a 1/256 unit lattice first avoids it
+pub fn round_coords<G: MapCoords<f64, f64, Output = G>>(g: &G) -> G {
+    g.map_coords(|c| geo::coord! {x: (c.x * 256.).round() / 256., y: (c.y * 256.).round() / 256.})
+}
+
 #[derive(PartialEq)]
 pub struct Cam {
     pub focus: Vec2,
@@ -69,6 +173,13 @@ impl Cam {
         self.grid_rect.h = self.screen_rect.h / self.scale;
     }
 
+    //Screen position (like mouse_position()) to world coordinates
+    pub fn screen_to_world(&self, screen: Vec2) -> Vec2 {
+        (screen - self.screen_rect.point()) * vec2(1.0, -1.0) / self.scale
+            + self.grid_rect.point()
+            + vec2(0.0, self.grid_rect.h)
+    }
+
     pub fn to_camera(&self) -> Camera2D {
         Camera2D {
             target: self.focus,
@@ -88,6 +199,42 @@ impl Cam {
     }
 }
 
+//Edits from a panel only merge while they belong to the same gesture, one press held on a
+//widget or one stretch of typing into it. Call this on every widget each frame, since the
+//focus start is only remembered on the frame the widget gains focus.
+pub fn gesture(response: &egui::Response) -> Option<u64> {
+    let ctx = &response.ctx;
+    let pressed = ctx.input(|i| {
+        i.pointer
+            .any_down()
+            .then(|| i.pointer.press_start_time())
+            .flatten()
+    });
+    if let Some(start) = pressed {
+        return Some(response.id.with(start.to_bits()).value());
+    }
+    if !response.has_focus() {
+        return None;
+    }
+    let key = response.id.with("focus_start");
+    if response.gained_focus() {
+        let now = ctx.input(|i| i.time);
+        ctx.data_mut(|d| d.insert_temp(key, now));
+    }
+    let start: f64 = ctx.data(|d| d.get_temp(key)).unwrap_or_default();
+    Some(response.id.with(start.to_bits()).value())
+}
+
+//The gesture of whichever of these widgets changed this frame
+pub fn changed_gesture(responses: &[egui::Response]) -> Option<u64> {
+    let gestures: Vec<_> = responses.iter().map(gesture).collect();
+    responses
+        .iter()
+        .zip(gestures)
+        .find(|(r, _)| r.changed())
+        .and_then(|(_, g)| g)
+}
+
 //toggle_ui() and toggle() functions by Emil "emilk" Ernerfeldt, released under MIT license
 fn toggle_ui(ui: &mut egui::Ui, on: &mut bool) -> egui::Response {
     let desired_size = ui.spacing().interact_size.y * egui::vec2(2.0, 1.0);